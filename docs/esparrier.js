@@ -11,6 +11,7 @@
  * - 'c' - Commit Config (writes config to flash and reboots)
  * - 'k' <bool> - Keep Awake (prevent device sleep)
  * - 'b' - Reboot (trigger software reset)
+ * - 'g' <width> <height> - Set Screen Size (u16 LE each, until next reboot)
  */
 
 const ESPARRIER_VID = 0x0d0a;
@@ -28,6 +29,13 @@ const CMD_WRITE_CONFIG = 'w'.charCodeAt(0);
 const CMD_COMMIT_CONFIG = 'c'.charCodeAt(0);
 const CMD_KEEP_AWAKE = 'k'.charCodeAt(0);
 const CMD_REBOOT = 'b'.charCodeAt(0);
+const CMD_FACTORY_RESET = 'f'.charCodeAt(0);
+const CMD_SET_SCREEN_SIZE = 'g'.charCodeAt(0);
+const CMD_SET_SERVER_CLIPBOARD = 't'.charCodeAt(0);
+const CMD_SELECT_CLIPBOARD_SLOT = 'l'.charCodeAt(0);
+const CMD_READ_LOG = 'L'.charCodeAt(0);
+const CMD_LOG_LEVEL = 'V'.charCodeAt(0);
+const CMD_RESET_REPORT = 'R'.charCodeAt(0);
 const CMD_OTA_START = 'O'.charCodeAt(0);
 const CMD_OTA_DATA = 'D'.charCodeAt(0);
 const CMD_OTA_ABORT = 'A'.charCodeAt(0);
@@ -38,13 +46,66 @@ const RESP_STATE = 's'.charCodeAt(0);
 const RESP_CONFIG = 'r'.charCodeAt(0);
 const RESP_OK = 'o'.charCodeAt(0);
 const RESP_ERROR = 'e'.charCodeAt(0);
+const RESP_LOG = 'L'.charCodeAt(0);
+const RESP_LOG_LEVEL = 'V'.charCodeAt(0);
+const RESP_RESET_REPORT = 'R'.charCodeAt(0);
 const RESP_OTA_PROGRESS = 'P'.charCodeAt(0);
 const RESP_OTA_COMPLETE = 'C'.charCodeAt(0);
 
+// Pages of the get state command
+const STATE_PAGE_STATS = 1;
+const STATE_PAGE_ERRORS = 2;
+
+// Log levels, the index is the level number
+const LOG_LEVELS = ['off', 'error', 'warn', 'info', 'debug', 'trace'];
+
+// Hardware reset reasons of the ESP32-S3
+const RESET_REASONS = {
+    0x01: 'Power on',
+    0x03: 'Software reset',
+    0x05: 'Deep sleep',
+    0x07: 'Watchdog (MWDT0)',
+    0x08: 'Watchdog (MWDT1)',
+    0x09: 'Watchdog (RTC)',
+    0x0B: 'CPU watchdog (MWDT0)',
+    0x0C: 'CPU software reset',
+    0x0D: 'CPU watchdog (RTC)',
+    0x0F: 'Brownout',
+    0x10: 'System watchdog (RTC)',
+    0x11: 'CPU watchdog (MWDT1)',
+    0x12: 'Super watchdog',
+    0x13: 'Clock glitch',
+    0x14: 'eFuse CRC error',
+    0x15: 'USB UART',
+    0x16: 'USB JTAG',
+    0x17: 'Power glitch'
+};
+
+// Why the firmware reset the board itself
+const RESET_CAUSES = ['none', 'panic', 'hid_timeout', 'task_stuck'];
+
+// Indicator status codes, the index is the code
+const INDICATOR_STATUSES = [
+    'none', 'wifi_connecting', 'wifi_connected', 'server_connecting', 'server_connected', 'active',
+    'typing', 'clipboard_slot', 'factory_reset', 'provisioning', 'identify'
+];
+
+// Barrier error codes in the error history
+const BARRIER_ERRORS = {
+    'd': 'Disconnected',
+    't': 'TCP connection failed',
+    'p': 'Invalid data received',
+    'b': 'Server is busy',
+    'u': 'Screen name unknown to the server',
+    'v': 'Incompatible protocol version',
+    'x': 'Server reported bad protocol'
+};
+
 // Error codes
 const ERR_ENDPOINT = 'e'.charCodeAt(0);
 const ERR_TIMEOUT = 't'.charCodeAt(0);
 const ERR_INVALID_CONFIG = 'i'.charCodeAt(0);
+const ERR_INVALID_ARGUMENT = 'a'.charCodeAt(0);
 const ERR_UNKNOWN_COMMAND = 'u'.charCodeAt(0);
 const ERR_OTA = 'O'.charCodeAt(0);
 
@@ -309,6 +370,7 @@ class EsparrierDevice {
             case ERR_ENDPOINT: return 'Endpoint error';
             case ERR_TIMEOUT: return 'Timeout';
             case ERR_INVALID_CONFIG: return 'Invalid configuration';
+            case ERR_INVALID_ARGUMENT: return 'Invalid argument';
             case ERR_UNKNOWN_COMMAND: return 'Unknown command';
             default: return `Unknown error (${String.fromCharCode(errorCode)})`;
         }
@@ -348,6 +410,19 @@ class EsparrierDevice {
             };
         }
 
+        // Parse active server endpoint if present (newer firmware only)
+        state.serverEndpoint = null;
+        if (response.length >= 20 && (response[14] !== 0 || response[15] !== 0 || response[16] !== 0 || response[17] !== 0)) {
+            const port = response[18] | (response[19] << 8);
+            state.serverEndpoint = `${response[14]}.${response[15]}.${response[16]}.${response[17]}:${port}`;
+        }
+
+        // Parse clipboard typing progress in percent (newer firmware only), null if not typing
+        state.typingProgress = null;
+        if (response.length >= 21 && response[20] !== 0xFF) {
+            state.typingProgress = response[20];
+        }
+
         // Add derived fields
         state.version = `${state.versionMajor}.${state.versionMinor}.${state.versionPatch}`;
         state.modelName = MODEL_NAMES[state.modelId] || `Unknown (${state.modelId})`;
@@ -368,6 +443,65 @@ class EsparrierDevice {
         return state;
     }
 
+    /**
+     * Get connection statistics since boot (newer firmware only)
+     */
+    async getStats() {
+        const response = await this.sendCommand([CMD_GET_STATE, STATE_PAGE_STATS]);
+
+        if (response[0] !== RESP_STATE) {
+            if (response[0] === RESP_ERROR) {
+                throw new Error(this.parseError(response[1]));
+            }
+            throw new Error('Unexpected response');
+        }
+
+        // 10 u32 LE values after response code, 12 on newer firmware
+        const view = new DataView(response.buffer, response.byteOffset + 1);
+        const value = (i) => (response.length >= 5 + i * 4 ? view.getUint32(i * 4, true) : null);
+        return {
+            uptime: value(0),
+            connectAttempts: value(1),
+            sessions: value(2),
+            sessionLength: value(3),
+            bytesIn: value(4),
+            bytesOut: value(5),
+            packetsIn: value(6),
+            packetsOut: value(7),
+            unknownPackets: value(8),
+            hidReportTimeouts: value(9),
+            resets: value(10),
+            unexpectedResets: value(11)
+        };
+    }
+
+    /**
+     * Get the most recent Barrier errors, newest first (newer firmware only)
+     */
+    async getErrorHistory() {
+        const response = await this.sendCommand([CMD_GET_STATE, STATE_PAGE_ERRORS]);
+
+        if (response[0] !== RESP_STATE) {
+            if (response[0] === RESP_ERROR) {
+                throw new Error(this.parseError(response[1]));
+            }
+            throw new Error('Unexpected response');
+        }
+
+        const view = new DataView(response.buffer, response.byteOffset);
+        const errors = [];
+        for (let i = 0; i < response[1]; i++) {
+            const offset = 2 + i * 5;
+            const code = String.fromCharCode(response[offset + 4]);
+            errors.push({
+                uptime: view.getUint32(offset, true),
+                code,
+                message: BARRIER_ERRORS[code] || `Unknown error (${code})`
+            });
+        }
+        return errors;
+    }
+
     /**
      * Read configuration from device
      */
@@ -461,6 +595,211 @@ class EsparrierDevice {
         return true;
     }
 
+    /**
+     * Erase the configuration and reboot with the default configuration
+     */
+    async factoryReset() {
+        const response = await this.sendCommand([CMD_FACTORY_RESET]);
+
+        if (response[0] !== RESP_OK) {
+            if (response[0] === RESP_ERROR) {
+                throw new Error(this.parseError(response[1]));
+            }
+            throw new Error('Factory reset command failed');
+        }
+
+        // Device will reboot, connection will be lost
+        return true;
+    }
+
+    /**
+     * Change the screen size without rebooting, the device re-announces the screen to the server
+     */
+    async setScreenSize(width, height) {
+        const response = await this.sendCommand([
+            CMD_SET_SCREEN_SIZE,
+            width & 0xFF, (width >> 8) & 0xFF,
+            height & 0xFF, (height >> 8) & 0xFF,
+        ]);
+
+        if (response[0] !== RESP_OK) {
+            if (response[0] === RESP_ERROR) {
+                throw new Error(this.parseError(response[1]));
+            }
+            throw new Error('Failed to set screen size');
+        }
+
+        return true;
+    }
+
+    /**
+     * Send text to the Barrier server clipboard (requires clipboard feature)
+     *
+     * The text is queued on the device and sent when the cursor is on its screen.
+     */
+    async setServerClipboard(text) {
+        const textBytes = new TextEncoder().encode(text);
+
+        if (textBytes.length > 1024) {
+            throw new Error('Text too large');
+        }
+
+        // Send command (device will immediately start receiving blocks)
+        await this.sendData([
+            CMD_SET_SERVER_CLIPBOARD,
+            textBytes.length & 0xFF, (textBytes.length >> 8) & 0xFF,
+        ]);
+
+        const blockCount = Math.ceil(textBytes.length / 64);
+        for (let i = 0; i < blockCount; i++) {
+            const block = new Uint8Array(64);
+            block.set(textBytes.subarray(i * 64, Math.min((i + 1) * 64, textBytes.length)));
+            await this.sendData(block);
+        }
+
+        const response = await this.receiveData();
+        if (response[0] !== RESP_OK) {
+            if (response[0] === RESP_ERROR) {
+                throw new Error(this.parseError(response[1]));
+            }
+            throw new Error('Failed to set server clipboard');
+        }
+
+        return true;
+    }
+
+    /**
+     * Select the clipboard history slot, 0 is the newest, and optionally start typing it
+     * (requires clipboard feature)
+     */
+    async selectClipboardSlot(slot, paste = false) {
+        const response = await this.sendCommand([CMD_SELECT_CLIPBOARD_SLOT, slot, paste ? 1 : 0]);
+
+        if (response[0] !== RESP_OK) {
+            if (response[0] === RESP_ERROR) {
+                throw new Error(this.parseError(response[1]));
+            }
+            throw new Error('Failed to select clipboard slot');
+        }
+
+        return true;
+    }
+
+    /**
+     * Read the log kept in the device RAM from the offset, returns the text and the offset to read
+     * the new lines from next time
+     */
+    async readLog(offset = 0) {
+        const chunks = [];
+        for (;;) {
+            const response = await this.sendCommand([
+                CMD_READ_LOG,
+                offset & 0xFF, (offset >> 8) & 0xFF, (offset >> 16) & 0xFF, (offset >>> 24) & 0xFF,
+            ]);
+
+            if (response[0] !== RESP_LOG) {
+                if (response[0] === RESP_ERROR) {
+                    throw new Error(this.parseError(response[1]));
+                }
+                throw new Error('Failed to read log');
+            }
+
+            const view = new DataView(response.buffer, response.byteOffset);
+            const start = view.getUint32(1, true);
+            const length = view.getUint16(5, true);
+            const end = view.getUint32(7, true);
+            const page = new Uint8Array(length);
+            for (let i = 0; i < length; i += 64) {
+                page.set((await this.receiveData()).subarray(0, length - i), i);
+            }
+            chunks.push(page);
+            offset = start + length;
+            if (length === 0 || offset >= end) {
+                break;
+            }
+        }
+
+        const text = new TextDecoder().decode(
+            chunks.reduce((all, chunk) => {
+                const joined = new Uint8Array(all.length + chunk.length);
+                joined.set(all);
+                joined.set(chunk, all.length);
+                return joined;
+            }, new Uint8Array(0))
+        );
+        return { text, offset };
+    }
+
+    /**
+     * Get why the board was reset last time, with the panic message and location if it panicked
+     */
+    async getResetReport() {
+        const response = await this.sendCommand([CMD_RESET_REPORT]);
+
+        if (response[0] !== RESP_RESET_REPORT) {
+            if (response[0] === RESP_ERROR) {
+                throw new Error(this.parseError(response[1]));
+            }
+            throw new Error('Failed to get reset report');
+        }
+
+        const view = new DataView(response.buffer, response.byteOffset);
+        const locationLength = response[8];
+        const messageLength = response[9];
+        const length = locationLength + messageLength;
+        const text = new Uint8Array(length);
+        for (let i = 0; i < length; i += 64) {
+            text.set((await this.receiveData()).subarray(0, length - i), i);
+        }
+        const decoder = new TextDecoder();
+        return {
+            reason: RESET_REASONS[response[1]] || `Unknown (${response[1]})`,
+            cause: RESET_CAUSES[response[2]] || `unknown (${response[2]})`,
+            indicatorStatus: INDICATOR_STATUSES[response[3]] || 'none',
+            location: decoder.decode(text.subarray(0, locationLength)),
+            line: view.getUint32(4, true),
+            message: decoder.decode(text.subarray(locationLength))
+        };
+    }
+
+    /**
+     * Get the log level, one of 'off', 'error', 'warn', 'info', 'debug' and 'trace'
+     */
+    async getLogLevel() {
+        const response = await this.sendCommand([CMD_LOG_LEVEL]);
+
+        if (response[0] !== RESP_LOG_LEVEL) {
+            if (response[0] === RESP_ERROR) {
+                throw new Error(this.parseError(response[1]));
+            }
+            throw new Error('Failed to get log level');
+        }
+
+        return LOG_LEVELS[response[1]];
+    }
+
+    /**
+     * Set the log level until reboot, one of 'off', 'error', 'warn', 'info', 'debug' and 'trace'
+     *
+     * Returns the level actually set, release builds can't go beyond 'info'
+     */
+    async setLogLevel(level) {
+        const index = LOG_LEVELS.indexOf(level);
+        if (index < 0) {
+            throw new Error('Invalid log level');
+        }
+        const response = await this.sendCommand([CMD_LOG_LEVEL, index]);
+
+        if (response[0] !== RESP_LOG_LEVEL) {
+            if (response[0] === RESP_ERROR) {
+                throw new Error(this.parseError(response[1]));
+            }
+            throw new Error('Failed to set log level');
+        }
+
+        return LOG_LEVELS[response[1]];
+    }
+
     /**
      * Set keep awake mode
      */
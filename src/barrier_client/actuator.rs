@@ -9,6 +9,12 @@ pub trait Actuator {
         &self,
     ) -> impl core::future::Future<Output = Result<(u16, u16), BarrierError>>;
 
+    fn set_screen_size(
+        &mut self,
+        width: u16,
+        height: u16,
+    ) -> impl core::future::Future<Output = Result<(), BarrierError>>;
+
     fn get_cursor_position(
         &self,
     ) -> impl core::future::Future<Output = Result<(u16, u16), BarrierError>>;
@@ -68,7 +74,7 @@ pub trait Actuator {
     #[cfg(feature = "clipboard")]
     fn set_clipboard(
         &mut self,
-        data: heapless::Vec<u8, { crate::constants::MAX_CLIPBOARD_SIZE }>,
+        data: super::clipboard::ClipboardData,
     ) -> impl core::future::Future<Output = Result<(), BarrierError>>;
 
     fn enter(
@@ -79,4 +85,19 @@ pub trait Actuator {
     ) -> impl core::future::Future<Output = Result<(), BarrierError>>;
 
     fn leave(&mut self) -> impl core::future::Future<Output = Result<(), BarrierError>>;
+
+    fn screensaver(
+        &mut self,
+        active: bool,
+    ) -> impl core::future::Future<Output = Result<(), BarrierError>>;
+
+    fn language_sync(
+        &mut self,
+        languages: &str,
+    ) -> impl core::future::Future<Output = Result<(), BarrierError>>;
+
+    fn secure_input(
+        &mut self,
+        app: &str,
+    ) -> impl core::future::Future<Output = Result<(), BarrierError>>;
 }
@@ -1,24 +1,48 @@
+use embassy_futures::select::{Either, select};
 use embassy_net::{IpEndpoint, Stack, tcp::TcpSocket};
 use embassy_time::{Duration, TimeoutError, with_timeout};
 use embedded_io_async::Write;
 use log::{debug, error, info, warn};
 
-use crate::get_running_state;
+#[cfg(feature = "clipboard")]
+use crate::clipboard::{restore_server_clipboard, take_server_clipboard};
+use crate::{
+    get_running_state, get_running_state_mut, get_screen_size,
+    health::{HealthTask, check_in},
+    running_state::{reconnect_requested, screen_size_changed, wait_reconnect_requested},
+};
 
 use super::{
     Actuator, BarrierError, packet::Packet, packet_io::PacketReader, packet_io::PacketWriter,
     packet_stream::PacketStream,
 };
 
+const HELLO_MAGICS: [&[u8; 7]; 2] = [b"Barrier", b"Synergy"];
+const PROTOCOL_MAJOR: u16 = 1;
+// 1.6 is the version of Barrier, 1.7 adds language synchronization, 1.8 adds secure input
+// notification
+const PROTOCOL_MINOR_MIN: u16 = 6;
+const PROTOCOL_MINOR_MAX: u16 = 8;
+// Connecting and the handshake must be done within this time
+const CONNECT_DEADLINE: Duration = Duration::from_secs(30);
+// The session loop comes around at least every jiggle interval, plus this margin
+const LOOP_DEADLINE_MARGIN: Duration = Duration::from_secs(10);
+
 #[cfg(feature = "clipboard")]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default)]
 pub enum ClipboardStage {
+    #[default]
     None,
     Mark1,
-    Mark2(usize),
+    Mark2(super::clipboard::ClipboardParser),
     Mark3,
 }
 
+/// Run a Barrier session until it ends.
+///
+/// Returns `Ok(())` if the server closed the session with a goodbye, `Err(TcpError)` if the
+/// connection could not be established, `Err(Disconnected)` if the connection dropped during the
+/// session, and the reason of the disconnection otherwise.
 #[allow(unused_assignments)]
 pub async fn start_barrier_client<Actor: Actuator>(
     endpoint: IpEndpoint,
@@ -27,7 +51,11 @@ pub async fn start_barrier_client<Actor: Actuator>(
     stack: Stack<'_>,
     mut actor: Actor,
 ) -> Result<(), BarrierError> {
-    let screen_size: (u16, u16) = actor.get_screen_size().await?;
+    check_in(HealthTask::Client, CONNECT_DEADLINE);
+    // Changes made before connecting are already picked up by the actuator
+    screen_size_changed();
+    reconnect_requested();
+    let mut screen_size: (u16, u16) = actor.get_screen_size().await?;
 
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
@@ -41,13 +69,13 @@ pub async fn start_barrier_client<Actor: Actuator>(
         .connect(endpoint)
         .await
         .inspect_err(|e| error!("Failed to connect: {e:?}"))
-        .map_err(|_| BarrierError::Disconnected)?;
+        .map_err(|_| BarrierError::TcpError)?;
     debug!("Connected");
 
     let _size = stream.read_packet_size().await?;
-    if stream.read_bytes_fixed::<7>().await? == [b'B', b'a', b'r', b'r', b'i', b'e', b'r'] {
-        debug!("Got hello");
-    } else {
+    // Barrier and Input Leap say "Barrier", Synergy and Deskflow say "Synergy"
+    let magic = stream.read_bytes_fixed::<7>().await?;
+    if !HELLO_MAGICS.contains(&&magic) {
         error!("Got invalid hello");
         return Err(BarrierError::ProtocolError(
             super::error::PacketError::FormatError,
@@ -55,35 +83,100 @@ pub async fn start_barrier_client<Actor: Actuator>(
     }
     let major = stream.read_u16().await?;
     let minor = stream.read_u16().await?;
-    debug!("Got hello {major}:{minor}");
+    debug!(
+        "Got hello '{}' {major}:{minor}",
+        core::str::from_utf8(&magic).unwrap_or("????")
+    );
+    if major != PROTOCOL_MAJOR || minor < PROTOCOL_MINOR_MIN {
+        error!("Unsupported protocol version {major}:{minor}");
+        return Err(BarrierError::IncompatibleVersion(major, minor));
+    }
+    // The server accepts any client with the same major version and a minor version not higher
+    // than its own
+    let minor = minor.min(PROTOCOL_MINOR_MAX);
+    info!("Using protocol version {PROTOCOL_MAJOR}.{minor}");
 
     stream
-        .write_u32("Barrier".len() as u32 + 2 + 2 + 4 + device_name.len() as u32)
+        .write_u32(magic.len() as u32 + 2 + 2 + 4 + device_name.len() as u32)
         .await?;
     stream
-        .write_all(b"Barrier")
+        .write_all(&magic)
         .await
         .map_err(|_| BarrierError::ProtocolError(super::error::PacketError::IoError))?;
-    stream.write_u16(1).await?;
-    stream.write_u16(6).await?;
+    stream.write_u16(PROTOCOL_MAJOR).await?;
+    stream.write_u16(minor).await?;
     stream.write_str(device_name).await?;
 
     actor.connected().await?;
+    get_running_state_mut().await.stats.session_started();
 
     #[cfg(feature = "clipboard")]
     let mut clipboard_stage = ClipboardStage::None;
+    // Sequence number of the enter while the screen is active, the server ignores clipboard grabs
+    // from inactive screens
+    #[cfg(feature = "clipboard")]
+    let mut seq: Option<u32> = None;
 
-    let mut packet_stream = PacketStream::new(stream);
-    loop {
-        match with_timeout(
+    let mut packet_stream = PacketStream::new(stream, minor);
+    let result = loop {
+        check_in(
+            HealthTask::Client,
+            Duration::from_secs(jiggle_interval as u64) + LOOP_DEADLINE_MARGIN,
+        );
+        if reconnect_requested() {
+            info!("Reconnect requested");
+            break Ok(());
+        }
+        // Only checked between packets, a read can't be dropped halfway through a packet
+        if screen_size_changed() {
+            let (width, height) = get_screen_size();
+            actor.set_screen_size(width, height).await?;
+            screen_size = actor.get_screen_size().await?;
+            // Unsolicited DINF, the server replies with CIAK and uses the new size right away
+            match packet_stream
+                .write(device_info(&actor, screen_size).await?)
+                .await
+            {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    error!("Error: {e:?}");
+                    actor.disconnected().await?;
+                    Err(BarrierError::Disconnected)
+                }
+            }?;
+        }
+        // The text waits until the screen is active, also across reconnects
+        #[cfg(feature = "clipboard")]
+        if let Some(seq) = seq
+            && let Some(text) = take_server_clipboard()
+        {
+            info!("Sending {} bytes to the server clipboard", text.len());
+            match packet_stream.write_clipboard(0, seq, &text).await {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    error!("Error: {e:?}");
+                    restore_server_clipboard(text);
+                    actor.disconnected().await?;
+                    Err(BarrierError::Disconnected)
+                }
+            }?;
+        }
+        let read = with_timeout(
             Duration::from_secs(jiggle_interval as u64),
             packet_stream.read(
                 #[cfg(feature = "clipboard")]
                 &mut clipboard_stage,
             ),
-        )
-        .await
-        {
+        );
+        // The session is dropped anyway, so a packet cut in half doesn't matter
+        let read = match select(wait_reconnect_requested(), read).await {
+            Either::First(_) => {
+                info!("Reconnect requested");
+                break Ok(());
+            }
+            Either::Second(read) => read,
+        };
+        match read {
             Err(TimeoutError) => {
                 // Periodical tasks
                 if get_running_state().await.keep_awake {
@@ -93,27 +186,20 @@ pub async fn start_barrier_client<Actor: Actuator>(
             }
             Ok(Err(e)) => {
                 error!("Error: {e:?}");
-                break;
+                break Err(BarrierError::Disconnected);
             }
             Ok(Ok(packet)) => {
                 match packet {
                     Packet::QueryInfo => {
                         match packet_stream
-                            .write(Packet::DeviceInfo {
-                                x: 0,
-                                y: 0,
-                                w: screen_size.0,
-                                h: screen_size.1,
-                                _dummy: 0,
-                                mx: 0,
-                                my: 0,
-                            })
+                            .write(device_info(&actor, screen_size).await?)
                             .await
                         {
                             Ok(_) => Ok(()),
                             Err(e) => {
+                                error!("Error: {e:?}");
                                 actor.disconnected().await?;
-                                Err(e)
+                                Err(BarrierError::Disconnected)
                             }
                         }?;
                     }
@@ -127,15 +213,14 @@ pub async fn start_barrier_client<Actor: Actuator>(
                                 Ok(())
                             }
                             Err(e) => {
+                                error!("Error: {e:?}");
                                 actor.disconnected().await?;
-                                Err(e)
+                                Err(BarrierError::Disconnected)
                             }
                         }?;
                     }
                     Packet::MouseMoveAbs { x, y } => {
-                        let abs_x = (x as u32 * 0x7fff).div_ceil(screen_size.0 as u32) as u16;
-                        let abs_y = (y as u32 * 0x7fff).div_ceil(screen_size.1 as u32) as u16;
-                        actor.set_cursor_position(abs_x, abs_y).await?;
+                        actor.set_cursor_position(x, y).await?;
                     }
                     Packet::MouseMove { x, y } => {
                         actor.move_cursor(x, y).await?;
@@ -166,24 +251,59 @@ pub async fn start_barrier_client<Actor: Actuator>(
                     Packet::CursorEnter {
                         x,
                         y,
-                        seq_num: _dummy,
+                        seq_num,
                         mask,
                     } => {
+                        #[cfg(feature = "clipboard")]
+                        {
+                            seq = Some(seq_num);
+                        }
+                        #[cfg(not(feature = "clipboard"))]
+                        let _ = seq_num;
                         actor.enter(x, y, mask).await?;
                     }
                     Packet::CursorLeave => {
+                        #[cfg(feature = "clipboard")]
+                        {
+                            seq = None;
+                        }
                         actor.leave().await?;
+                        // Tell the server where the cursor was left, so it can be put back there
+                        // when the screen is entered again
+                        match packet_stream
+                            .write(device_info(&actor, screen_size).await?)
+                            .await
+                        {
+                            Ok(_) => Ok(()),
+                            Err(e) => {
+                                error!("Error: {e:?}");
+                                actor.disconnected().await?;
+                                Err(BarrierError::Disconnected)
+                            }
+                        }?;
                     }
                     Packet::GrabClipboard { id, seq_num } => {
                         debug!("Grab clipboard: id:{id}, seq_num:{seq_num}");
                     }
                     #[cfg(feature = "clipboard")]
                     Packet::SetClipboard { id, seq_num, data } => {
-                        debug!("Set clipboard: id:{id}, seq_num:{seq_num}, data:{data:?}");
+                        debug!(
+                            "Set clipboard: id:{id}, seq_num:{seq_num}, length:{:?}",
+                            data.as_ref().map(|d| d.len())
+                        );
                         if let Some(data) = data {
                             actor.set_clipboard(data).await?;
                         }
                     }
+                    Packet::ScreenSaver { active } => {
+                        actor.screensaver(active).await?;
+                    }
+                    Packet::LanguageSync { languages } => {
+                        actor.language_sync(&languages).await?;
+                    }
+                    Packet::SecureInput { app } => {
+                        actor.secure_input(&app).await?;
+                    }
                     Packet::DeviceInfo { .. }
                     | Packet::ClientNoOp
                     | Packet::InfoAck
@@ -192,25 +312,26 @@ pub async fn start_barrier_client<Actor: Actuator>(
                     }
                     Packet::ServerBusy => {
                         warn!("Server is busy, disconnecting");
-                        break;
+                        break Err(BarrierError::ServerBusy);
                     }
                     Packet::GoodBye => {
                         info!("Goodbye");
-                        break;
+                        break Ok(());
                     }
                     Packet::BadProtocol => {
                         error!("Bad protocol");
-                        break;
+                        break Err(BarrierError::BadProtocol);
                     }
                     Packet::UnknownDevice => {
                         error!("Unknown device");
-                        break;
+                        break Err(BarrierError::UnknownDevice);
                     }
                     Packet::IncompatibleVersion { major, minor } => {
                         error!("Incompatible version: {major}:{minor}");
-                        break;
+                        break Err(BarrierError::IncompatibleVersion(major, minor));
                     }
                     Packet::Unknown(cmd) => {
+                        get_running_state_mut().await.stats.unknown_packets += 1;
                         log::info!(
                             "Unknown packet code: '{}' ({:02X} {:02X} {:02X} {:02X})",
                             core::str::from_utf8(&cmd).unwrap_or("????"),
@@ -223,7 +344,23 @@ pub async fn start_barrier_client<Actor: Actuator>(
                 }
             }
         }
-    }
+    };
     actor.disconnected().await?;
-    Err(BarrierError::Disconnected)
+    result
+}
+
+async fn device_info<Actor: Actuator>(
+    actor: &Actor,
+    screen_size: (u16, u16),
+) -> Result<Packet, BarrierError> {
+    let (mx, my) = actor.get_cursor_position().await?;
+    Ok(Packet::DeviceInfo {
+        x: 0,
+        y: 0,
+        w: screen_size.0,
+        h: screen_size.1,
+        _dummy: 0,
+        mx,
+        my,
+    })
 }
@@ -1,10 +1,13 @@
 use core::cmp::min;
 
-use heapless::Vec;
+use log::warn;
 
-use crate::constants::MAX_CLIPBOARD_SIZE;
+use crate::constants::MAX_CLIPBOARD_HEAP_SIZE;
 
-use super::{error::PacketError, packet_io::PacketReader};
+use super::error::PacketError;
+
+/// Clipboard text received from the server, kept on the heap so packets stay small
+pub type ClipboardData = alloc::vec::Vec<u8>;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u8)]
@@ -14,49 +17,240 @@ pub enum ClipboardFormat {
     Bitmap = 2,
 }
 
-pub async fn parse_clipboard<T: PacketReader>(
-    stream: &mut T,
-) -> Result<(Option<Vec<u8, MAX_CLIPBOARD_SIZE>>, usize), PacketError> {
-    let mut consumed: usize = 0;
-
-    let _sz = stream.read_u32().await?;
-    consumed += 4;
-    let mut ret = Vec::new();
-    let num_formats = stream.read_u32().await?;
-    consumed += 4;
-
-    for _ in 0..num_formats {
-        let format = stream.read_u32().await?;
-        consumed += 4;
-        let mut length = stream.read_u32().await? as usize;
-        consumed += 4;
-
-        let format = match format {
-            0 => ClipboardFormat::Text,
-            1 => ClipboardFormat::Html,
-            2 => ClipboardFormat::Bitmap,
-            _ => Err(PacketError::FormatError)?,
-        };
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum ParserState {
+    #[default]
+    NumFormats,
+    Format,
+    Length,
+    Data,
+    Done,
+}
+
+/// Incremental parser of the clipboard data, which may be split into many mark 2 chunks.
+///
+/// Only the text format is kept, up to `MAX_CLIPBOARD_HEAP_SIZE` bytes. The text buffer is
+/// allocated once its length is known, so receiving never needs room for a reallocation.
+#[derive(Default)]
+pub struct ClipboardParser {
+    state: ParserState,
+    // Big endian u32 header field being read, may span chunks
+    field: [u8; 4],
+    field_len: usize,
+    // Formats not finished yet
+    formats: u32,
+    format: Option<ClipboardFormat>,
+    // Data bytes left in the current format
+    remaining: usize,
+    text: ClipboardData,
+    truncated: bool,
+}
+
+impl core::fmt::Debug for ClipboardParser {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ClipboardParser")
+            .field("state", &self.state)
+            .field("formats", &self.formats)
+            .field("remaining", &self.remaining)
+            .field("text_len", &self.text.len())
+            .finish()
+    }
+}
+
+impl ClipboardParser {
+    /// Feed the next piece of the clipboard data
+    pub fn feed(&mut self, mut data: &[u8]) -> Result<(), PacketError> {
+        while !data.is_empty() {
+            match self.state {
+                ParserState::Done => break,
+                ParserState::Data => {
+                    let n = min(self.remaining, data.len());
+                    if self.format == Some(ClipboardFormat::Text) {
+                        self.push_text(&data[..n]);
+                    }
+                    self.remaining -= n;
+                    data = &data[n..];
+                    if self.remaining == 0 {
+                        self.next_format();
+                    }
+                }
+                _ => {
+                    let n = min(self.field.len() - self.field_len, data.len());
+                    self.field[self.field_len..self.field_len + n].copy_from_slice(&data[..n]);
+                    self.field_len += n;
+                    data = &data[n..];
+                    if self.field_len == self.field.len() {
+                        self.field_len = 0;
+                        self.header_field(u32::from_be_bytes(self.field))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The text format of the clipboard, `None` if there is no text
+    pub fn finish(self) -> Option<ClipboardData> {
+        if self.state != ParserState::Done {
+            warn!("Clipboard data ended unexpectedly");
+        }
+        if self.truncated {
+            warn!("Clipboard truncated to {} bytes", self.text.len());
+        }
+        if self.text.is_empty() {
+            None
+        } else {
+            // The data may be shorter than the length announced
+            let mut text = self.text;
+            text.shrink_to_fit();
+            Some(text)
+        }
+    }
 
-        if format == ClipboardFormat::Text {
-            while length > 0 {
-                let mut buf = [0; 16];
-                let read_length = min(length, 16);
-                stream
-                    .read_exact(&mut buf[0..read_length])
-                    .await
-                    .map_err(|_| PacketError::IoError)?;
-                consumed += read_length;
-                length -= read_length;
-                if ret.len() < (MAX_CLIPBOARD_SIZE - read_length) {
-                    ret.extend_from_slice(&buf[0..read_length]).unwrap();
+    fn header_field(&mut self, value: u32) -> Result<(), PacketError> {
+        match self.state {
+            ParserState::NumFormats => {
+                self.formats = value;
+                self.state = if value == 0 {
+                    ParserState::Done
+                } else {
+                    ParserState::Format
+                };
+            }
+            ParserState::Format => {
+                self.format = Some(match value {
+                    0 => ClipboardFormat::Text,
+                    1 => ClipboardFormat::Html,
+                    2 => ClipboardFormat::Bitmap,
+                    _ => Err(PacketError::FormatError)?,
+                });
+                self.state = ParserState::Length;
+            }
+            ParserState::Length => {
+                self.remaining = value as usize;
+                if self.format == Some(ClipboardFormat::Text) {
+                    self.reserve_text();
+                }
+                if self.remaining == 0 {
+                    self.next_format();
+                } else {
+                    self.state = ParserState::Data;
                 }
             }
+            ParserState::Data | ParserState::Done => {}
+        }
+        Ok(())
+    }
+
+    fn next_format(&mut self) {
+        self.formats -= 1;
+        self.state = if self.formats == 0 {
+            ParserState::Done
+        } else {
+            ParserState::Format
+        };
+    }
+
+    fn reserve_text(&mut self) {
+        let n = min(self.remaining, MAX_CLIPBOARD_HEAP_SIZE - self.text.len());
+        // Running out of heap must not bring the whole device down, the text is truncated to
+        // what is already reserved
+        if self.text.try_reserve_exact(n).is_err() {
+            warn!(
+                "Not enough memory for a clipboard of {} bytes",
+                self.remaining
+            );
+        }
+    }
+
+    fn push_text(&mut self, data: &[u8]) {
+        // Never grows past the room reserved, which is what makes the heap use predictable
+        let n = min(data.len(), self.text.capacity() - self.text.len());
+        self.text.extend_from_slice(&data[..n]);
+        self.truncated |= n < data.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use super::{ClipboardParser, PacketError};
+    use crate::constants::MAX_CLIPBOARD_HEAP_SIZE;
+
+    /// Clipboard data as sent in mark 2 chunks, the formats are (format, data) pairs
+    fn clipboard_data(formats: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(formats.len() as u32).to_be_bytes());
+        for (format, content) in formats {
+            data.extend_from_slice(&format.to_be_bytes());
+            data.extend_from_slice(&(content.len() as u32).to_be_bytes());
+            data.extend_from_slice(content);
         }
+        data
     }
-    if ret.is_empty() {
-        Ok((None, consumed))
-    } else {
-        Ok((Some(ret), consumed))
+
+    fn parse(data: &[u8], chunk_size: usize) -> Result<Option<Vec<u8>>, PacketError> {
+        let mut parser = ClipboardParser::default();
+        for chunk in data.chunks(chunk_size) {
+            parser.feed(chunk)?;
+        }
+        Ok(parser.finish())
+    }
+
+    #[test]
+    fn test_split_chunks() {
+        let data = clipboard_data(&[(1, b"<b>Hello</b>"), (0, b"Hello"), (2, b"BM")]);
+        // Every split point, including the middle of the headers
+        for chunk_size in 1..=data.len() {
+            assert_eq!(
+                parse(&data, chunk_size).unwrap().as_deref(),
+                Some(b"Hello".as_ref()),
+                "chunk size {chunk_size}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_non_text_formats() {
+        let data = clipboard_data(&[(1, b"<i>x</i>"), (2, b"BM")]);
+        assert_eq!(parse(&data, 3).unwrap(), None);
+        // No formats at all, and empty text
+        assert_eq!(parse(&clipboard_data(&[]), 1).unwrap(), None);
+        assert_eq!(parse(&clipboard_data(&[(0, b"")]), 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let text = [b'x'; MAX_CLIPBOARD_HEAP_SIZE + 100];
+        let data = clipboard_data(&[(0, &text), (1, b"<p>")]);
+        let mut parser = ClipboardParser::default();
+        for chunk in data.chunks(1000) {
+            parser.feed(chunk).unwrap();
+        }
+        assert!(parser.truncated);
+        let text = parser.finish().unwrap();
+        assert_eq!(text.len(), MAX_CLIPBOARD_HEAP_SIZE);
+        assert_eq!(text.capacity(), MAX_CLIPBOARD_HEAP_SIZE);
+    }
+
+    #[test]
+    fn test_malformed_length() {
+        // The text is shorter than its length, the data ends in the middle of it
+        let mut data = clipboard_data(&[(0, b"Hello")]);
+        data[11] = 100;
+        assert_eq!(parse(&data, 4).unwrap().as_deref(), Some(b"Hello".as_ref()));
+        // A huge length doesn't reserve more than the limit
+        data[8] = 0xFF;
+        let mut parser = ClipboardParser::default();
+        parser.feed(&data).unwrap();
+        assert!(parser.text.capacity() <= MAX_CLIPBOARD_HEAP_SIZE);
+        // Unknown format
+        let data = clipboard_data(&[(7, b"Hello")]);
+        assert!(matches!(parse(&data, 2), Err(PacketError::FormatError)));
+        // Anything after the last format is ignored
+        let mut data = clipboard_data(&[(0, b"Hello")]);
+        data.extend_from_slice(b"garbage");
+        assert_eq!(parse(&data, 5).unwrap().as_deref(), Some(b"Hello".as_ref()));
     }
 }
@@ -20,4 +20,12 @@ pub enum BarrierError {
     TcpError,
     #[error("invalid data received")]
     ProtocolError(#[from] PacketError),
+    #[error("server is busy")]
+    ServerBusy,
+    #[error("screen name unknown to the server")]
+    UnknownDevice,
+    #[error("incompatible protocol version {0}.{1}")]
+    IncompatibleVersion(u16, u16),
+    #[error("server reported bad protocol")]
+    BadProtocol,
 }
@@ -9,4 +9,6 @@ mod packet_stream;
 
 pub use actuator::Actuator;
 pub use client::start_barrier_client;
+#[cfg(feature = "clipboard")]
+pub use clipboard::ClipboardData;
 pub use error::BarrierError;
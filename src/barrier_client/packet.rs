@@ -29,7 +29,7 @@ pub enum Packet {
     SetClipboard {
         id: u8,
         seq_num: u32,
-        data: Option<heapless::Vec<u8, { crate::constants::MAX_CLIPBOARD_SIZE }>>,
+        data: Option<super::clipboard::ClipboardData>,
     },
     CursorEnter {
         x: u16,
@@ -80,6 +80,17 @@ pub enum Packet {
         major: u16,
         minor: u16,
     },
+    ScreenSaver {
+        active: bool,
+    },
+    // Keyboard languages of the server, 2-letter codes with the active one first, protocol 1.7
+    LanguageSync {
+        languages: heapless::String<64>,
+    },
+    // Name of the app enabled secure input on the server, protocol 1.8
+    SecureInput {
+        app: heapless::String<64>,
+    },
     Unknown([u8; 4]),
 }
 
@@ -134,6 +145,17 @@ impl Packet {
                 out.write_str("EUNK").await?;
                 Ok(())
             }
+            Packet::GrabClipboard { id, seq_num } => {
+                let mut buf = [0u8; 4 + 4 + 1 + 4];
+                buf[0..4].copy_from_slice((4u32 + 1 + 4).to_be_bytes().as_ref());
+                buf[4..8].copy_from_slice(b"CCLP");
+                buf[8] = id;
+                buf[9..13].copy_from_slice(seq_num.to_be_bytes().as_ref());
+                out.write_all(&buf)
+                    .await
+                    .map_err(|_| PacketError::IoError)?;
+                Ok(())
+            }
             Packet::MouseMoveAbs { x, y } => {
                 let mut buf = [0u8; 4 + 4 + 2 + 2];
                 buf[0..4].copy_from_slice((4u32 + 2 + 2).to_be_bytes().as_ref());
@@ -152,3 +174,31 @@ impl Packet {
         }
     }
 }
+
+/// Write a `DCLP` packet, the payload is the concatenation of `parts`.
+#[cfg(feature = "clipboard")]
+pub async fn write_clipboard_data<W: AsyncWrite + Unpin>(
+    mut out: W,
+    id: u8,
+    seq_num: u32,
+    mark: u8,
+    parts: &[&[u8]],
+) -> Result<(), PacketError> {
+    let len: usize = parts.iter().map(|p| p.len()).sum();
+    let mut buf = [0u8; 4 + 4 + 1 + 4 + 1 + 4];
+    buf[0..4].copy_from_slice((4 + 1 + 4 + 1 + 4 + len as u32).to_be_bytes().as_ref());
+    buf[4..8].copy_from_slice(b"DCLP");
+    buf[8] = id;
+    buf[9..13].copy_from_slice(seq_num.to_be_bytes().as_ref());
+    buf[13] = mark;
+    buf[14..18].copy_from_slice((len as u32).to_be_bytes().as_ref());
+    out.write_all(&buf)
+        .await
+        .map_err(|_| PacketError::IoError)?;
+    for part in parts {
+        out.write_all(part)
+            .await
+            .map_err(|_| PacketError::IoError)?;
+    }
+    Ok(())
+}
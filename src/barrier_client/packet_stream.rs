@@ -1,18 +1,33 @@
-use embedded_io_async::Read as AsyncRead;
+use embedded_io_async::{ErrorType, Read as AsyncRead, Write as AsyncWrite};
 use log::debug;
 
+use crate::running_state::{TrafficStats, add_traffic};
+
 #[cfg(feature = "clipboard")]
-use crate::barrier_client::{client::ClipboardStage, clipboard::parse_clipboard};
+use crate::barrier_client::{
+    client::ClipboardStage,
+    clipboard::{ClipboardFormat, ClipboardParser},
+    packet::write_clipboard_data,
+};
 
 use super::{error::PacketError, packet::Packet, packet_io::PacketReader, packet_io::PacketWriter};
 
+/// Size of each mark 2 chunk when sending the clipboard to the server
+#[cfg(feature = "clipboard")]
+const CLIPBOARD_CHUNK_SIZE: usize = 512;
+
+/// Minimum protocol minor version required by the packets introduced after 1.6
+const VERSIONED_PACKETS: [(&[u8; 4], u16); 2] = [(b"LSYN", 7), (b"SECN", 8)];
+
 pub struct PacketStream<S: PacketReader + PacketWriter> {
     stream: S,
+    // Negotiated protocol minor version
+    minor: u16,
 }
 
 impl<S: PacketReader + PacketWriter> PacketStream<S> {
-    pub fn new(stream: S) -> Self {
-        Self { stream }
+    pub fn new(stream: S, minor: u16) -> Self {
+        Self { stream, minor }
     }
 
     pub async fn read(
@@ -20,6 +35,11 @@ impl<S: PacketReader + PacketWriter> PacketStream<S> {
         #[cfg(feature = "clipboard")] clipboard_stage: &mut ClipboardStage,
     ) -> Result<Packet, PacketError> {
         let size = self.stream.read_packet_size().await?;
+        add_traffic(TrafficStats {
+            bytes_in: size.saturating_add(4),
+            packets_in: 1,
+            ..Default::default()
+        });
         if size < 4 {
             let mut buf = [0; 4];
             self.stream
@@ -31,6 +51,7 @@ impl<S: PacketReader + PacketWriter> PacketStream<S> {
         Self::do_read(
             &mut self.stream,
             size as usize,
+            self.minor,
             #[cfg(feature = "clipboard")]
             clipboard_stage,
         )
@@ -40,11 +61,22 @@ impl<S: PacketReader + PacketWriter> PacketStream<S> {
     async fn do_read<T: AsyncRead + Unpin>(
         chunk: &mut T,
         mut limit: usize,
+        minor: u16,
         #[cfg(feature = "clipboard")] clipboard_stage: &mut ClipboardStage,
     ) -> Result<Packet, PacketError> {
         let code: [u8; 4] = chunk.read_bytes_fixed().await?;
         limit -= 4;
 
+        if VERSIONED_PACKETS
+            .iter()
+            .any(|(c, required)| **c == code && minor < *required)
+        {
+            // Not part of the negotiated protocol version, the server shouldn't send it
+            debug!("Ignoring packet not supported by protocol 1.{minor}");
+            chunk.discard_exact(limit).await?;
+            return Ok(Packet::Unknown(code));
+        }
+
         let packet = match code.as_ref() {
             b"QINF" => Packet::QueryInfo,
             b"CIAK" => Packet::InfoAck,
@@ -102,48 +134,37 @@ impl<S: PacketReader + PacketWriter> PacketStream<S> {
                 // mark 2 is the actual data and is split into chunks
                 // mark 3 is an empty chunk
                 debug!("Current Clipboard stage: {clipboard_stage:?}");
-                *clipboard_stage = match mark {
-                    1 => match clipboard_stage {
-                        ClipboardStage::None => ClipboardStage::Mark1,
-                        ClipboardStage::Mark3 => ClipboardStage::Mark1,
-                        _ => {
-                            warn!("Unexpected clipboard stage: {clipboard_stage:?}");
-                            ClipboardStage::None
-                        }
-                    },
-                    2 => match clipboard_stage {
-                        // 1st mark 2 chunk
-                        ClipboardStage::Mark1 => ClipboardStage::Mark2(0),
-                        ClipboardStage::Mark2(idx) => ClipboardStage::Mark2(*idx + 1),
-                        _ => {
-                            warn!("Unexpected clipboard stage: {clipboard_stage:?}");
-                            ClipboardStage::None
-                        }
-                    },
-                    3 => match clipboard_stage {
-                        ClipboardStage::Mark2(_) => ClipboardStage::Mark3,
-                        _ => {
-                            warn!("Unexpected clipboard stage: {clipboard_stage:?}");
-                            ClipboardStage::None
-                        }
-                    },
-                    _ => {
-                        warn!("Unexpected clipboard mark: {mark}");
+                let mut packet = Packet::SetClipboard {
+                    id,
+                    seq_num,
+                    data: None,
+                };
+                *clipboard_stage = match (mark, core::mem::take(clipboard_stage)) {
+                    (1, ClipboardStage::None | ClipboardStage::Mark3) => ClipboardStage::Mark1,
+                    // The clipboard data is split into as many mark 2 chunks as needed
+                    (2, ClipboardStage::Mark1) => {
+                        let mut parser = ClipboardParser::default();
+                        read_clipboard_chunk(chunk, &mut limit, &mut parser).await?;
+                        ClipboardStage::Mark2(parser)
+                    }
+                    (2, ClipboardStage::Mark2(mut parser)) => {
+                        read_clipboard_chunk(chunk, &mut limit, &mut parser).await?;
+                        ClipboardStage::Mark2(parser)
+                    }
+                    (3, ClipboardStage::Mark2(parser)) => {
+                        packet = Packet::SetClipboard {
+                            id,
+                            seq_num,
+                            data: parser.finish(),
+                        };
+                        ClipboardStage::Mark3
+                    }
+                    (mark, stage) => {
+                        warn!("Unexpected clipboard mark {mark} in stage: {stage:?}");
                         ClipboardStage::None
                     }
                 };
-                // We only process the 1st mark 2 chunk
-                if *clipboard_stage == ClipboardStage::Mark2(0) {
-                    let (data, consumed) = parse_clipboard(chunk).await?;
-                    limit -= consumed;
-                    Packet::SetClipboard { id, seq_num, data }
-                } else {
-                    Packet::SetClipboard {
-                        id,
-                        seq_num,
-                        data: None,
-                    }
-                }
+                packet
             }
             b"DMUP" => {
                 let id = chunk.read_i8().await?;
@@ -203,6 +224,19 @@ impl<S: PacketReader + PacketWriter> PacketStream<S> {
                 limit -= 2;
                 Packet::IncompatibleVersion { major, minor }
             }
+            b"CSEC" => {
+                let active = chunk.read_u8().await? != 0;
+                limit -= 1;
+                Packet::ScreenSaver { active }
+            }
+            b"LSYN" => {
+                let languages = read_short_str(chunk, &mut limit).await?;
+                Packet::LanguageSync { languages }
+            }
+            b"SECN" => {
+                let app = read_short_str(chunk, &mut limit).await?;
+                Packet::SecureInput { app }
+            }
             b"CROP" => Packet::ResetOptions,
             b"EBAD" => Packet::BadProtocol,
             b"CBYE" => Packet::GoodBye,
@@ -233,6 +267,147 @@ impl<S: PacketReader + PacketWriter> PacketStream<S> {
     }
 
     pub async fn write(&mut self, packet: Packet) -> Result<(), PacketError> {
-        packet.write_wire(&mut self.stream).await
+        add_traffic(TrafficStats {
+            packets_out: 1,
+            ..Default::default()
+        });
+        packet
+            .write_wire(CountingWriter {
+                inner: &mut self.stream,
+            })
+            .await
+    }
+
+    /// Grab the server clipboard and fill it with `text`.
+    ///
+    /// The clipboard data is sent in 3 stages, mark 1 is the total size as a decimal string, mark 2
+    /// is the data split into chunks, and mark 3 is an empty chunk to finish the transfer.
+    #[cfg(feature = "clipboard")]
+    pub async fn write_clipboard(
+        &mut self,
+        id: u8,
+        seq_num: u32,
+        text: &[u8],
+    ) -> Result<(), PacketError> {
+        use core::fmt::Write;
+
+        self.write(Packet::GrabClipboard { id, seq_num }).await?;
+
+        // The clipboard data only has the text format
+        let mut header = [0u8; 12];
+        header[0..4].copy_from_slice(1u32.to_be_bytes().as_ref());
+        header[4..8].copy_from_slice((ClipboardFormat::Text as u32).to_be_bytes().as_ref());
+        header[8..12].copy_from_slice((text.len() as u32).to_be_bytes().as_ref());
+        let total = header.len() + text.len();
+
+        let mut size = heapless::String::<10>::new();
+        write!(size, "{total}").ok();
+        self.write_clipboard_data(id, seq_num, 1, &[size.as_bytes()])
+            .await?;
+
+        let mut offset = 0;
+        while offset < total {
+            let end = (offset + CLIPBOARD_CHUNK_SIZE).min(total);
+            let h = &header[offset.min(header.len())..end.min(header.len())];
+            let t = &text[offset.saturating_sub(header.len())..end.saturating_sub(header.len())];
+            self.write_clipboard_data(id, seq_num, 2, &[h, t]).await?;
+            offset = end;
+        }
+
+        self.write_clipboard_data(id, seq_num, 3, &[]).await
+    }
+
+    #[cfg(feature = "clipboard")]
+    async fn write_clipboard_data(
+        &mut self,
+        id: u8,
+        seq_num: u32,
+        mark: u8,
+        parts: &[&[u8]],
+    ) -> Result<(), PacketError> {
+        add_traffic(TrafficStats {
+            packets_out: 1,
+            ..Default::default()
+        });
+        write_clipboard_data(
+            CountingWriter {
+                inner: &mut self.stream,
+            },
+            id,
+            seq_num,
+            mark,
+            parts,
+        )
+        .await
+    }
+}
+
+/// Feed the string in a mark 2 chunk to the clipboard parser
+#[cfg(feature = "clipboard")]
+async fn read_clipboard_chunk<T: AsyncRead + Unpin>(
+    chunk: &mut T,
+    limit: &mut usize,
+    parser: &mut ClipboardParser,
+) -> Result<(), PacketError> {
+    let len = chunk.read_u32().await? as usize;
+    *limit = limit.saturating_sub(4);
+    let mut len = len.min(*limit);
+    let mut buf = [0; 64];
+    while len > 0 {
+        let n = len.min(buf.len());
+        chunk
+            .read_exact(&mut buf[..n])
+            .await
+            .map_err(|_| PacketError::InsufficientDataError)?;
+        *limit -= n;
+        len -= n;
+        parser.feed(&buf[..n])?;
+    }
+    Ok(())
+}
+
+/// Read a string, keep the first 64 bytes, the rest is left to be discarded with the packet
+async fn read_short_str<T: AsyncRead + Unpin>(
+    chunk: &mut T,
+    limit: &mut usize,
+) -> Result<heapless::String<64>, PacketError> {
+    let len = chunk.read_u32().await? as usize;
+    *limit -= 4;
+    let mut buf = [0; 64];
+    let n = len.min(buf.len()).min(*limit);
+    chunk
+        .read_exact(&mut buf[..n])
+        .await
+        .map_err(|_| PacketError::InsufficientDataError)?;
+    *limit -= n;
+    // The string may be truncated in the middle of a UTF-8 sequence
+    let s = match core::str::from_utf8(&buf[..n]) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&buf[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    Ok(s.try_into().unwrap_or_default())
+}
+
+/// Counts the bytes written into the inner writer as outgoing traffic
+struct CountingWriter<'a, W> {
+    inner: &'a mut W,
+}
+
+impl<W: ErrorType> ErrorType for CountingWriter<'_, W> {
+    type Error = W::Error;
+}
+
+impl<W: AsyncWrite> AsyncWrite for CountingWriter<'_, W> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let n = self.inner.write(buf).await?;
+        add_traffic(TrafficStats {
+            bytes_out: n as u32,
+            ..Default::default()
+        });
+        Ok(n)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().await
     }
 }
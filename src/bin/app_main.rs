@@ -22,7 +22,8 @@ use log::{debug, error, info, warn};
 use esparrier::constants::*;
 
 use esparrier::{
-    AppConfig, IndicatorStatus, UsbActuator, mk_static, set_indicator_status, start_barrier_client,
+    AppConfig, HealthTask, IndicatorStatus, Reconnect, UsbActuator, check_health, check_in,
+    check_out, get_running_state_mut, mk_static, set_indicator_status, start_barrier_client,
     start_hid_task, start_indicator_task,
 };
 
@@ -30,6 +31,10 @@ use esparrier::{
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
 
+// The network must be up again within this time after it went down, otherwise the watchdog resets
+// the board, which also resets a wedged WiFi or Ethernet stack
+const NETWORK_DEADLINE: Duration = Duration::from_secs(300);
+
 #[main]
 async fn main(spawner: Spawner) {
     #[cfg(not(any(feature = "wifi", feature = "ethernet")))]
@@ -38,7 +43,7 @@ async fn main(spawner: Spawner) {
     #[cfg(all(feature = "wifi", feature = "ethernet"))]
     compile_error!("Only one of the features 'wifi' or 'ethernet' can be enabled");
 
-    esp_println::logger::init_logger_from_env();
+    esparrier::init_logger();
 
     println!(
         "Firmware version: {} {}",
@@ -47,8 +52,12 @@ async fn main(spawner: Spawner) {
     );
 
     let peripherals = esp_hal::init(esp_hal::Config::default().with_cpu_clock(CpuClock::max()));
+    esparrier::init_reset_report().await;
 
     esp_alloc::heap_allocator!(size: 160 * 1024);
+    // Large clipboards go to PSRAM if the board has it
+    #[cfg(feature = "psram")]
+    esp_alloc::psram_allocator!(peripherals.PSRAM, esp_hal::psram);
 
     // Setup Embassy
     // let systimer = SystemTimer::new(peripherals.SYSTIMER);
@@ -79,6 +88,11 @@ async fn main(spawner: Spawner) {
         .spawn(esparrier::button_task())
         .inspect_err(|e| error!("Failed to start button task: {e:?}"))
         .unwrap();
+    #[cfg(feature = "clipboard")]
+    spawner
+        .spawn(esparrier::paste_task())
+        .inspect_err(|e| error!("Failed to start paste task: {e:?}"))
+        .unwrap();
 
     // Setup indicator
     start_indicator_task(spawner).await;
@@ -103,6 +117,11 @@ async fn main(spawner: Spawner) {
         // Disable power saving for maximum performance
         controller.set_power_saving(PowerSaveMode::None).ok();
 
+        // Serve the config form on an access point instead if there is no usable config
+        if esparrier::is_provisioning_required() {
+            esparrier::start_provisioning(spawner, controller, interfaces.ap, seed).await;
+        }
+
         let wifi_interface = interfaces.sta;
 
         // Init network stack
@@ -190,6 +209,11 @@ async fn main(spawner: Spawner) {
         stack
     };
 
+    if AppConfig::get().syslog.is_some() {
+        spawner.must_spawn(esparrier::syslog_task(stack));
+    }
+
+    check_in(HealthTask::Network, NETWORK_DEADLINE);
     info!("Waiting for net link up...");
     loop {
         if stack.is_link_up() {
@@ -209,29 +233,75 @@ async fn main(spawner: Spawner) {
         Timer::after(Duration::from_millis(500)).await;
     }
 
+    check_out(HealthTask::Network);
+    // Set while the network is down and watched
+    let mut network_down = false;
+
+    let servers = AppConfig::get().get_server_endpoints();
+    // Index of the server to try next, stays on the last one that worked
+    let mut current = 0;
+    let mut reconnect = Reconnect::new(&AppConfig::get().reconnect);
     loop {
+        if stack.is_link_up() && stack.config_v4().is_some() {
+            check_out(HealthTask::Network);
+            network_down = false;
+        } else if !network_down {
+            warn!("Network is down");
+            check_in(HealthTask::Network, NETWORK_DEADLINE);
+            network_down = true;
+        }
+        let (endpoint, screen_name) = servers[current];
+        info!("Connecting to Barrier server #{current} {endpoint} as '{screen_name}'");
+        {
+            let mut state = get_running_state_mut().await;
+            state.server_endpoint = Some(endpoint);
+            state.stats.connect_attempts += 1;
+        }
         // Start the Barrier client
         let actuator = UsbActuator::default();
-        start_barrier_client(
-            AppConfig::get().get_server_endpoint(),
-            &AppConfig::get().screen_name,
+        let result = start_barrier_client(
+            endpoint,
+            screen_name,
             AppConfig::get().jiggle_interval,
             stack,
             actuator,
         )
-        .await
-        .inspect_err(|e| {
-            warn!("Disconnected from Barrier, error: {e:?}, reconnecting in 5 seconds...")
-        })
-        .ok();
-        Timer::after(Duration::from_millis(5000)).await;
+        .await;
+        let session = {
+            let mut state = get_running_state_mut().await;
+            if let Err(e) = &result {
+                state.stats.record_error(e);
+            }
+            state.stats.session_ended()
+        };
+        let decision = reconnect.next(&result, session, rng.random());
+        if decision.next_server {
+            // Fail over to the next server
+            current = (current + 1) % servers.len();
+        }
+        match result {
+            Err(e) => warn!(
+                "Disconnected from Barrier, error: {e:?}, connecting to server #{current} in {}ms...",
+                decision.delay.as_millis()
+            ),
+            Ok(_) => info!(
+                "Barrier server said goodbye, reconnecting in {}ms...",
+                decision.delay.as_millis()
+            ),
+        }
+        // The client is not running, but the loop must come back after the delay
+        check_in(HealthTask::Client, decision.delay + Duration::from_secs(10));
+        Timer::after(decision.delay).await;
     }
 }
 
+/// Feed the watchdog while all tasks are alive, a stuck task resets the board
 #[embassy_executor::task]
 async fn watchdog_task(watchdog: &'static mut Wdt<TIMG1<'static>>) {
     loop {
-        watchdog.feed();
+        if check_health() {
+            watchdog.feed();
+        }
         Timer::after(Duration::from_millis(500)).await;
     }
 }
@@ -239,9 +309,27 @@ async fn watchdog_task(watchdog: &'static mut Wdt<TIMG1<'static>>) {
 #[cfg(feature = "wifi")]
 #[embassy_executor::task]
 async fn wifi_task(mut controller: esp_radio::wifi::WifiController<'static>) {
-    use esp_radio::wifi::{ClientConfig, ModeConfig, WifiEvent, WifiStaState};
+    use embassy_futures::select::{Either, select};
 
     debug!("start connection task");
+    loop {
+        // New settings from Improv are tried before they are saved, the controller is set up with
+        // the saved settings again afterwards
+        if let Either::Second(settings) = select(
+            keep_wifi_connected(&mut controller),
+            esparrier::wait_wifi_settings(),
+        )
+        .await
+        {
+            esparrier::answer_wifi_settings(&mut controller, settings, None).await;
+        }
+    }
+}
+
+#[cfg(feature = "wifi")]
+async fn keep_wifi_connected(controller: &mut esp_radio::wifi::WifiController<'static>) {
+    use esp_radio::wifi::{ClientConfig, ModeConfig, WifiEvent, WifiStaState};
+
     loop {
         if esp_radio::wifi::sta_state() == WifiStaState::Connected {
             // wait until we're no longer connected
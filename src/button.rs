@@ -0,0 +1,80 @@
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+use log::{info, warn};
+
+use crate::{
+    AppConfig, ButtonAction, GestureRecognizer, cancel_paste, clipboard::cycle_clipboard_slot,
+    config::factory_reset, constants::PASTE_BUTTON_PIN, get_running_state_mut, request_paste,
+};
+
+async fn run_action(action: ButtonAction) {
+    info!("Button action: {action:?}");
+    match action {
+        ButtonAction::None => {}
+        ButtonAction::Paste => request_paste(),
+        ButtonAction::CancelPaste => cancel_paste(),
+        ButtonAction::ToggleKeepAwake => {
+            let mut state = get_running_state_mut().await;
+            state.keep_awake = !state.keep_awake;
+            info!("Keep awake: {}", state.keep_awake);
+        }
+        ButtonAction::CycleClipboardSlot => cycle_clipboard_slot().await,
+        ButtonAction::Reboot => {
+            info!("Rebooting...");
+            esp_hal::system::software_reset()
+        }
+        #[cfg(feature = "wifi")]
+        ButtonAction::Provisioning => crate::provisioning::request_provisioning(),
+        #[cfg(not(feature = "wifi"))]
+        ButtonAction::Provisioning => {
+            warn!("Button action {action:?} is not supported");
+        }
+        ButtonAction::FactoryReset => factory_reset().await,
+    }
+}
+
+#[embassy_executor::task]
+pub async fn button_task() {
+    let button = unsafe { esp_hal::gpio::AnyPin::steal(PASTE_BUTTON_PIN) };
+    use embedded_hal_async::digital::Wait;
+    let input = esp_hal::gpio::Input::new(
+        button,
+        esp_hal::gpio::InputConfig::default().with_pull(esp_hal::gpio::Pull::Up),
+    );
+    let config = &AppConfig::get().button;
+    // The button is active low
+    let mut pressed = input.is_low();
+    // GPIO0 is a strapping pin, held at power-up it enters the ROM download mode instead, so a
+    // press seen here came after the reset and is not a boot hold
+    let held_at_boot = pressed && PASTE_BUTTON_PIN != 0;
+    let mut gestures = GestureRecognizer::new(config, held_at_boot, Instant::now().as_millis());
+    let mut debouncer = async_debounce::Debouncer::new(input, Duration::from_millis(50));
+
+    loop {
+        let edge = async {
+            if pressed {
+                debouncer.wait_for_rising_edge().await.ok();
+            } else {
+                debouncer.wait_for_falling_edge().await.ok();
+            }
+        };
+        let deadline = gestures
+            .deadline()
+            .map_or(Instant::MAX, Instant::from_millis);
+        let gesture = match select(edge, Timer::at(deadline)).await {
+            Either::First(_) => {
+                pressed = !pressed;
+                let now = Instant::now().as_millis();
+                if pressed {
+                    gestures.press(now)
+                } else {
+                    gestures.release(now)
+                }
+            }
+            Either::Second(_) => gestures.poll(Instant::now().as_millis()),
+        };
+        if let Some(gesture) = gesture {
+            run_action(config.action(gesture)).await;
+        }
+    }
+}
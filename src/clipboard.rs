@@ -1,81 +1,230 @@
-use embassy_time::Duration;
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use alloc::sync::Arc;
+
+use critical_section::Mutex;
+use embassy_futures::select::{Either, select};
 use log::{debug, info};
 
-use crate::{HidReport, constants::*, send_hid_report};
+use crate::{
+    AppConfig, ClipboardData, HidReport, IndicatorStatus, TypingLayout, constants::*,
+    restore_indicator_status, send_hid_report, set_indicator_status, synergy_hid::ReportType,
+    typing::type_text,
+};
+
+/// Recent clipboards received from the server, slot 0 is the newest
+///
+/// The clipboards are on the heap, and take at most `MAX_CLIPBOARD_HEAP_SIZE` bytes in total.
+/// They are shared with the paste task so typing a large clipboard doesn't hold the lock.
+struct ClipboardHistory {
+    entries: heapless::Deque<Arc<ClipboardData>, CLIPBOARD_HISTORY_SIZE>,
+    // Slot to be typed
+    selected: usize,
+}
 
 static CLIPBOARD_STORAGE: embassy_sync::mutex::Mutex<
     embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
-    Option<heapless::Vec<u8, MAX_CLIPBOARD_SIZE>>,
-> = embassy_sync::mutex::Mutex::new(None);
+    ClipboardHistory,
+> = embassy_sync::mutex::Mutex::new(ClipboardHistory {
+    entries: heapless::Deque::new(),
+    selected: 0,
+});
+
+// Text to be sent to the server clipboard, picked up by the Barrier client
+static SERVER_CLIPBOARD: embassy_sync::signal::Signal<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    heapless::Vec<u8, MAX_CLIPBOARD_SIZE>,
+> = embassy_sync::signal::Signal::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PasteRequest {
+    Start,
+    Cancel,
+}
+
+static PASTE_REQUEST: embassy_sync::signal::Signal<
+    embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+    PasteRequest,
+> = embassy_sync::signal::Signal::new();
+
+// Typing layout switched at runtime, overrides the config until next reboot, 0xFF means not set
+static TYPING_LAYOUT: AtomicU8 = AtomicU8::new(0xFF);
+
+// Set while the clipboard is being typed
+static TYPING: AtomicBool = AtomicBool::new(false);
+
+// Last keyboard report sent for the server, the keys it holds are pressed again after typing is
+// cancelled
+static SERVER_KEYBOARD_REPORT: Mutex<Cell<[u8; 9]>> = Mutex::new(Cell::new([
+    ReportType::Keyboard as u8,
+    0,
+    0,
+    0,
+    0,
+    0,
+    0,
+    0,
+    0,
+]));
 
-const KEY_PRESS_INTERVAL: Duration = Duration::from_millis(5);
+pub fn get_typing_layout() -> TypingLayout {
+    TypingLayout::from_u8(TYPING_LAYOUT.load(Ordering::Relaxed))
+        .unwrap_or(AppConfig::get().typing_layout)
+}
+
+pub fn set_typing_layout(layout: TypingLayout) {
+    info!("Typing layout changed to {layout:?}");
+    TYPING_LAYOUT.store(layout as u8, Ordering::Relaxed);
+}
+
+/// Start typing the selected clipboard slot, cancels typing if it is already in progress.
+pub fn request_paste() {
+    PASTE_REQUEST.signal(PasteRequest::Start);
+}
+
+/// Stop typing the clipboard if typing is in progress.
+pub fn cancel_paste() {
+    if is_typing() {
+        PASTE_REQUEST.signal(PasteRequest::Cancel);
+    }
+}
+
+pub fn is_typing() -> bool {
+    TYPING.load(Ordering::Relaxed)
+}
+
+/// Remember the keyboard report about to be sent for the server.
+pub(crate) fn set_server_keyboard_report(report: [u8; 9]) {
+    critical_section::with(|cs| SERVER_KEYBOARD_REPORT.borrow(cs).set(report));
+}
 
 async fn send_clipboard() {
-    info!("Paste button pressed, sending clipboard...");
-    let data = CLIPBOARD_STORAGE.lock().await.clone();
+    info!("Paste requested, sending clipboard...");
+    let data = {
+        let history = CLIPBOARD_STORAGE.lock().await;
+        history.entries.iter().nth(history.selected).cloned()
+    };
+    // The lock is released, the history may drop the clipboard while it is being typed
     if let Some(data) = data {
         debug!(
             "Clipboard (first 16 bytes): {:?}",
             &data.as_slice()[0..core::cmp::min(data.len(), 16)]
         );
-        for byte in data {
-            // Ignore non-ASCII characters
-            if byte > 0x7F {
-                continue;
-            }
-            let [k, m] = crate::synergy_hid::ASCII_2_HID[byte as usize];
-            if k == 0 {
-                continue;
-            }
-            let mut report = crate::synergy_hid::KeyboardReport::default();
-            if m != 0 {
-                // A key with a modifier
-                // Press modifier key
-                send_hid_report(HidReport::keyboard(report.press(m))).await;
-                embassy_time::Timer::after(KEY_PRESS_INTERVAL).await;
-                // Press key
-                send_hid_report(HidReport::keyboard(report.press(k))).await;
-                embassy_time::Timer::after(KEY_PRESS_INTERVAL).await;
-                // Release key
-                send_hid_report(HidReport::keyboard(report.release(k))).await;
-                embassy_time::Timer::after(KEY_PRESS_INTERVAL).await;
-                // Release modifier key
-                send_hid_report(HidReport::keyboard(report.release(m))).await;
-                embassy_time::Timer::after(KEY_PRESS_INTERVAL).await;
-            } else {
-                // A key without a modifier
-                // Press key
-                send_hid_report(HidReport::keyboard(report.press(k))).await;
-                embassy_time::Timer::after(KEY_PRESS_INTERVAL).await;
-                // Release key
-                send_hid_report(HidReport::keyboard(report.release(k))).await;
-                embassy_time::Timer::after(KEY_PRESS_INTERVAL).await;
-            }
-        }
+        type_text(
+            data.as_slice(),
+            get_typing_layout(),
+            &AppConfig::get().typing,
+        )
+        .await;
     }
 }
 
-pub async fn set_clipboard(data: heapless::Vec<u8, MAX_CLIPBOARD_SIZE>) {
+pub async fn set_clipboard(data: ClipboardData) {
     debug!(
         "Set clipboard: length: {}, data: {:?}",
         data.len(),
         &data[0..core::cmp::min(data.len(), 16)]
     );
-    CLIPBOARD_STORAGE.lock().await.replace(data);
+    let mut history = CLIPBOARD_STORAGE.lock().await;
+    // The server sends the same clipboard every time the screen is entered
+    if history.entries.front().map(|d| d.as_slice()) != Some(data.as_slice()) {
+        // Drop the oldest clipboards to make room for the new one
+        let mut total: usize = history.entries.iter().map(|d| d.len()).sum();
+        while history.entries.is_full()
+            || (!history.entries.is_empty() && total + data.len() > MAX_CLIPBOARD_HEAP_SIZE)
+        {
+            total -= history.entries.pop_back().map_or(0, |d| d.len());
+        }
+        history.entries.push_front(Arc::new(data)).ok();
+    }
+    history.selected = 0;
 }
 
-#[embassy_executor::task]
-pub async fn button_task() {
-    let button = unsafe { esp_hal::gpio::AnyPin::steal(PASTE_BUTTON_PIN) };
-    use embedded_hal_async::digital::Wait;
-    let input = esp_hal::gpio::Input::new(
-        button,
-        esp_hal::gpio::InputConfig::default().with_pull(esp_hal::gpio::Pull::Up),
-    );
-    let mut debouncer = async_debounce::Debouncer::new(input, Duration::from_millis(50));
+/// Select the clipboard slot to be typed, slot 0 is the newest, returns `false` if the slot is
+/// empty.
+pub async fn select_clipboard_slot(slot: usize) -> bool {
+    let mut history = CLIPBOARD_STORAGE.lock().await;
+    if slot >= history.entries.len() {
+        return false;
+    }
+    history.selected = slot;
+    drop(history);
+    info!("Clipboard slot {slot} selected");
+    set_indicator_status(IndicatorStatus::ClipboardSlot(slot as u8)).await;
+    true
+}
+
+/// Select the next older clipboard slot, wraps around to the newest one.
+pub async fn cycle_clipboard_slot() {
+    let (selected, len) = {
+        let history = CLIPBOARD_STORAGE.lock().await;
+        (history.selected, history.entries.len())
+    };
+    if len > 0 {
+        select_clipboard_slot((selected + 1) % len).await;
+    }
+}
+
+/// First `N` bytes of the clipboard slot, with non-printable characters replaced by spaces.
+pub async fn get_clipboard_preview<const N: usize>(slot: usize) -> Option<heapless::String<N>> {
+    let history = CLIPBOARD_STORAGE.lock().await;
+    let data = history.entries.iter().nth(slot)?;
+    Some(
+        data.iter()
+            .take(N)
+            .map(|&c| {
+                if (0x20..0x7F).contains(&c) {
+                    c as char
+                } else {
+                    ' '
+                }
+            })
+            .collect(),
+    )
+}
 
+/// Number of clipboards in the history
+pub async fn get_clipboard_count() -> usize {
+    CLIPBOARD_STORAGE.lock().await.entries.len()
+}
+
+/// Send the text to the server clipboard, the Barrier client sends it when the screen is active,
+/// replaces the previous text if it has not been sent yet.
+pub fn set_server_clipboard(text: heapless::Vec<u8, MAX_CLIPBOARD_SIZE>) {
+    debug!("Set server clipboard: length: {}", text.len());
+    SERVER_CLIPBOARD.signal(text);
+}
+
+pub fn take_server_clipboard() -> Option<heapless::Vec<u8, MAX_CLIPBOARD_SIZE>> {
+    SERVER_CLIPBOARD.try_take()
+}
+
+/// Put back the text that could not be sent, unless newer text is already waiting.
+pub fn restore_server_clipboard(text: heapless::Vec<u8, MAX_CLIPBOARD_SIZE>) {
+    if !SERVER_CLIPBOARD.signaled() {
+        SERVER_CLIPBOARD.signal(text);
+    }
+}
+
+#[embassy_executor::task]
+pub async fn paste_task() {
     loop {
-        debouncer.wait_for_rising_edge().await.ok();
-        send_clipboard().await;
+        if PASTE_REQUEST.wait().await != PasteRequest::Start {
+            continue;
+        }
+        TYPING.store(true, Ordering::Relaxed);
+        // Any request while typing cancels it, e.g. the paste button pressed again
+        if let Either::Second(_) = select(send_clipboard(), PASTE_REQUEST.wait()).await {
+            info!("Paste cancelled");
+            // A key may be held when typing is interrupted, only the keys held on the server stay
+            // down, e.g. the one that cancelled typing
+            let report = critical_section::with(|cs| SERVER_KEYBOARD_REPORT.borrow(cs).get());
+            send_hid_report(HidReport::Keyboard(report)).await;
+        }
+        TYPING.store(false, Ordering::Relaxed);
+        restore_indicator_status().await;
     }
 }
@@ -1,22 +1,27 @@
 use core::{
     cmp::{max, min},
+    fmt::Write,
     str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use embassy_net::{Config, IpEndpoint, Ipv4Address, Ipv4Cidr, StaticConfigV4};
 use embassy_sync::{
     blocking_mutex::raw::CriticalSectionRawMutex, once_lock::OnceLock, rwlock::RwLock,
 };
-use embedded_storage::{ReadStorage, Storage};
+use embedded_storage::{ReadStorage, Storage, nor_flash::NorFlash};
 use esp_storage::FlashStorage;
 use heapless::{String, Vec};
-use log::{debug, warn};
+use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
 
-use crate::constants::*;
+use crate::{
+    ButtonConfig, IndicatorStatus, ReconnectPolicy, ScreenMapping, SyslogConfig, TypingConfig,
+    TypingLayout, constants::*, set_indicator_status,
+};
 
 // Flash has a sector size of 4KB
-const MAX_CONFIG_SIZE: usize = 4096;
+pub(crate) const MAX_CONFIG_SIZE: usize = 4096;
 // Default NVS partition address, must be the same as the one in the partition table
 // @see partition_single_app.csv
 const NVS_PARTITION_ADDRESS: u32 = 0x9000;
@@ -56,26 +61,98 @@ impl<const N: usize> AsRef<str> for Secret<N> {
     }
 }
 
+/// Maximum number of additional Barrier servers
+pub const MAX_SERVERS: usize = 4;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ServerConfig {
+    pub server: String<64>,
+    // Use `AppConfig::screen_name` if not set
+    #[serde(default)]
+    pub screen_name: Option<String<64>>,
+}
+
+/// Maximum number of keyboard languages handled by language sync
+pub const MAX_LANGUAGES: usize = 4;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LanguageSync {
+    // Language code announced by the server, e.g. "en" or "de"
+    pub language: String<8>,
+    // Server key codes pressed together to switch the host layout, e.g. Super+Space
+    #[serde(default)]
+    pub chord: Vec<u16, 4>,
+    // Switch the clipboard typing layout
+    #[serde(default)]
+    pub typing_layout: Option<TypingLayout>,
+}
+
 #[allow(dead_code)]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AppConfig {
     // These fields must be set
     pub ssid: String<32>,
     pub password: Secret<64>,
+    // Can be empty if `servers` is set
+    #[serde(default)]
     pub server: String<64>,
     pub screen_name: String<64>,
 
+    // Backup Barrier servers, tried in order after `server` fails
+    #[serde(default)]
+    pub servers: Vec<ServerConfig, MAX_SERVERS>,
+    // Delays between reconnection attempts, optional
+    #[serde(default)]
+    pub reconnect: ReconnectPolicy,
+
     // Screen configuration
     #[serde(default = "get_default_screen_width")]
     pub screen_width: u16,
     #[serde(default = "get_default_screen_height")]
     pub screen_height: u16,
+    // Where the screen is located in the host virtual desktop, optional
+    // Only needed if the host has multiple monitors or the cursor position needs calibration
+    #[serde(default)]
+    pub screen_mapping: ScreenMapping,
     #[serde(default)]
     pub flip_wheel: bool,
     #[serde(default = "get_default_polling_rate")]
     pub polling_rate: u16,
     #[serde(default = "get_default_jiggle_interval")]
     pub jiggle_interval: u16,
+    // Server key code mapped to the Apple Fn/Globe key, optional
+    // Also adds the Apple vendor report to the HID descriptor, macOS hosts only
+    #[serde(default)]
+    pub apple_fn_key: Option<u16>,
+    // HID button number (1-8) for each server mouse button, 0 disables the button, optional
+    // Server buttons not listed here use the default mapping
+    #[serde(default)]
+    pub mouse_button_map: Vec<u8, 8>,
+    // Move the mouse to wake the host display up when the server screensaver is deactivated
+    #[serde(default)]
+    pub screensaver_wake: bool,
+    // Server key codes pressed together to lock the host when the server screensaver is activated,
+    // e.g. Super+L, optional
+    #[serde(default)]
+    pub screensaver_lock_chord: Vec<u16, 4>,
+    // Server key codes pressed together to start typing the clipboard, not forwarded to the host
+    #[serde(default)]
+    pub paste_hotkey: Vec<u16, 4>,
+    // Server key codes pressed together to stop typing the clipboard
+    #[serde(default)]
+    pub cancel_paste_hotkey: Vec<u16, 4>,
+    // Delays and options of the clipboard typing, optional
+    #[serde(default)]
+    pub typing: TypingConfig,
+    // Host keyboard layout used to type the clipboard
+    #[serde(default)]
+    pub typing_layout: TypingLayout,
+    // What to do when the server announces a keyboard language, optional
+    #[serde(default)]
+    pub language_sync: Vec<LanguageSync, MAX_LANGUAGES>,
+    // Button gestures and their actions, optional
+    #[serde(default)]
+    pub button: ButtonConfig,
 
     // Indicator brightness, used by both SmartLED and graphical indicators
     #[serde(default = "get_default_brightness")]
@@ -92,6 +169,9 @@ pub struct AppConfig {
     // Gateway IP address, optional
     #[serde(default)]
     gateway: Option<String<16>>,
+    // Syslog server the log is sent to, optional
+    #[serde(default)]
+    pub syslog: Option<SyslogConfig>,
 
     // USB HID configuration
     #[serde(default = "get_default_vid")]
@@ -159,15 +239,29 @@ impl Default for AppConfig {
             password: Secret::from_str(WIFI_PASSWORD).unwrap(),
             server: String::from_str(BARRIER_SERVER).unwrap(),
             screen_name: String::from_str(SCREEN_NAME).unwrap(),
+            servers: Vec::new(),
+            reconnect: ReconnectPolicy::default(),
             screen_width: SCREEN_WIDTH,
             screen_height: SCREEN_HEIGHT,
+            screen_mapping: ScreenMapping::default(),
             polling_rate: POLLING_RATE,
             jiggle_interval: JIGGLE_INTERVAL,
             flip_wheel: REVERSED_WHEEL,
+            apple_fn_key: None,
+            mouse_button_map: Vec::new(),
+            screensaver_wake: false,
+            screensaver_lock_chord: Vec::new(),
+            paste_hotkey: Vec::new(),
+            cancel_paste_hotkey: Vec::new(),
+            typing: TypingConfig::default(),
+            typing_layout: TypingLayout::default(),
+            language_sync: Vec::new(),
+            button: ButtonConfig::default(),
             brightness: BRIGHTNESS,
             ip_addr: None,
             dns_server: Vec::new(),
             gateway: None,
+            syslog: None,
             vid: USB_VID,
             pid: USB_PID,
             manufacturer: String::from_str(USB_MANUFACTURER).unwrap(),
@@ -179,6 +273,8 @@ impl Default for AppConfig {
 }
 
 static CONFIG: OnceLock<AppConfig> = OnceLock::new();
+// Set if the config is loaded from flash, otherwise the compile-time defaults are used
+static CONFIG_LOADED: AtomicBool = AtomicBool::new(false);
 static FLASH_STORAGE: OnceLock<RwLock<CriticalSectionRawMutex, FlashStorage>> = OnceLock::new();
 
 impl AppConfig {
@@ -189,6 +285,11 @@ impl AppConfig {
         debug!("Config initialized: {:?}", Self::get());
     }
 
+    /// Returns `false` if the config in flash is missing or invalid
+    pub fn is_loaded() -> bool {
+        CONFIG_LOADED.load(Ordering::Relaxed)
+    }
+
     pub fn get() -> &'static Self {
         // CONFIG.get_or_init(Self::load)
         CONFIG.try_get().expect("Config not initialized")
@@ -204,8 +305,12 @@ impl AppConfig {
         flash.read(flash_addr, &mut bytes).unwrap();
         // Find the valid JSON range
         let bytes = json_range(&bytes);
-        serde_json_core::from_slice(bytes)
+        serde_json_core::from_slice::<Self>(bytes)
             .map(|(c, _)| c)
+            .map_err(ConfigStoreError::from)
+            // Saved before the values were checked
+            .and_then(|c| c.validate().map(|_| c))
+            .inspect(|_| CONFIG_LOADED.store(true, Ordering::Relaxed))
             .inspect_err(|e| {
                 warn!("Failed to load config, using default, error: {e:?}");
                 debug!(
@@ -216,23 +321,134 @@ impl AppConfig {
             .unwrap_or_default()
     }
 
-    pub fn get_server_endpoint(&self) -> IpEndpoint {
-        parse_endpoint(&self.server)
+    /// All configured Barrier servers in order, with the screen name to be used for each of them
+    pub fn get_server_endpoints(&self) -> Vec<(IpEndpoint, &str), { MAX_SERVERS + 1 }> {
+        let mut ret = Vec::new();
+        let servers = Some((self.server.as_str(), &self.screen_name))
+            .filter(|(server, _)| !server.is_empty())
+            .into_iter()
+            .chain(self.servers.iter().map(|server| {
+                let screen_name = server.screen_name.as_ref().unwrap_or(&self.screen_name);
+                (server.server.as_str(), screen_name)
+            }));
+        for (server, screen_name) in servers {
+            if let Some(endpoint) = parse_endpoint(server) {
+                ret.push((endpoint, screen_name.as_str())).ok();
+            } else {
+                warn!("Invalid Barrier server '{server}', skipped");
+            }
+        }
+        if ret.is_empty() {
+            warn!("No Barrier server configured, using default");
+            let endpoint = parse_endpoint(BARRIER_SERVER).expect("invalid default server");
+            ret.push((endpoint, self.screen_name.as_str())).ok();
+        }
+        ret
     }
 
     pub fn get_ip_config(&self) -> Config {
-        match self.ip_addr.as_ref().map(|s| parse_cidr(s)) {
+        let address = self.ip_addr.as_ref().and_then(|s| {
+            parse_cidr(s).or_else(|| {
+                warn!("Invalid IP address '{s}', using DHCP");
+                None
+            })
+        });
+        match address {
             Some(addr) => Config::ipv4_static(StaticConfigV4 {
                 address: addr,
-                dns_servers: self.dns_server.iter().map(|s| parse_addr(s)).collect(),
-                gateway: self.gateway.as_ref().map(|s| parse_addr(s)), // Gateway is optional if server is on the same subnet
+                dns_servers: self
+                    .dns_server
+                    .iter()
+                    .filter_map(|s| parse_addr(s))
+                    .collect(),
+                gateway: self.gateway.as_ref().and_then(|s| parse_addr(s)), // Gateway is optional if server is on the same subnet
             }),
             None => Config::dhcpv4(Default::default()),
         }
     }
 
+    pub fn get_syslog_endpoint(&self) -> Option<IpEndpoint> {
+        self.syslog
+            .as_ref()
+            .and_then(|syslog| Some(IpEndpoint::from((parse_addr(&syslog.host)?, syslog.port))))
+    }
+
+    /// Value of a setting by name, only the settings with a single value are supported
+    pub fn get_field(&self, name: &str) -> Option<String<128>> {
+        let mut value = String::new();
+        match name {
+            "ssid" => write!(value, "{}", self.ssid),
+            "password" => write!(value, "{:?}", self.password),
+            "server" => write!(value, "{}", self.server),
+            "screen_name" => write!(value, "{}", self.screen_name),
+            "screen_width" => write!(value, "{}", self.screen_width),
+            "screen_height" => write!(value, "{}", self.screen_height),
+            "flip_wheel" => write!(value, "{}", self.flip_wheel),
+            "polling_rate" => write!(value, "{}", self.polling_rate),
+            "jiggle_interval" => write!(value, "{}", self.jiggle_interval),
+            "brightness" => write!(value, "{}", self.brightness),
+            "ip_addr" => write!(value, "{}", self.ip_addr.as_deref().unwrap_or_default()),
+            "gateway" => write!(value, "{}", self.gateway.as_deref().unwrap_or_default()),
+            "webusb_url" => write!(value, "{}", self.webusb_url.as_deref().unwrap_or_default()),
+            _ => return None,
+        }
+        .ok()?;
+        Some(value)
+    }
+
+    /// Change a setting by name, an empty value clears the optional ones
+    pub fn set_field(&mut self, name: &str, value: &str) -> Result<(), ConfigStoreError> {
+        fn parse<T: FromStr>(value: &str) -> Result<T, ConfigStoreError> {
+            value.parse().map_err(|_| ConfigStoreError::SerdeError)
+        }
+        fn optional<const N: usize>(value: &str) -> Result<Option<String<N>>, ConfigStoreError> {
+            Some(value).filter(|v| !v.is_empty()).map(parse).transpose()
+        }
+        match name {
+            "ssid" => self.ssid = parse(value)?,
+            "password" => self.password = parse(value)?,
+            "server" => self.server = parse(value)?,
+            "screen_name" => self.screen_name = parse(value)?,
+            "screen_width" => self.screen_width = parse(value)?,
+            "screen_height" => self.screen_height = parse(value)?,
+            "flip_wheel" => self.flip_wheel = parse(value)?,
+            "polling_rate" => self.polling_rate = parse(value)?,
+            "jiggle_interval" => self.jiggle_interval = parse(value)?,
+            "brightness" => self.brightness = parse(value)?,
+            "ip_addr" => self.ip_addr = optional(value)?,
+            "gateway" => self.gateway = optional(value)?,
+            "webusb_url" => self.webusb_url = optional(value)?,
+            _ => return Err(ConfigStoreError::UnknownCommand),
+        }
+        Ok(())
+    }
+
+    /// Check the values serde accepts but the firmware can't use, a saved config must never stop
+    /// the board from booting.
+    pub fn validate(&self) -> Result<(), ConfigStoreError> {
+        if self.server.is_empty() && self.servers.is_empty() {
+            return Err(ConfigStoreError::NoServer);
+        }
+        let mut servers = Some(&self.server)
+            .filter(|server| !server.is_empty())
+            .into_iter()
+            .chain(self.servers.iter().map(|server| &server.server));
+        let valid = self.polling_rate != 0
+            && self.screen_mapping.is_valid()
+            && servers.all(|server| parse_endpoint(server).is_some())
+            && self.ip_addr.iter().all(|s| parse_cidr(s).is_some())
+            && self.gateway.iter().all(|s| parse_addr(s).is_some())
+            && self.dns_server.iter().all(|s| parse_addr(s).is_some())
+            && self.syslog.iter().all(|s| parse_addr(&s.host).is_some());
+        if valid {
+            Ok(())
+        } else {
+            Err(ConfigStoreError::InvalidValue)
+        }
+    }
+
     pub fn get_polling_interval(&self) -> u8 {
-        let polling_interval = 1000 / self.polling_rate;
+        let polling_interval = 1000 / max(self.polling_rate, 1);
         if polling_interval < 1 {
             1
         } else if polling_interval > 255 {
@@ -251,36 +467,30 @@ fn json_range(buf: &[u8]) -> &[u8] {
     &buf[..end]
 }
 
-fn parse_addr(s: &str) -> Ipv4Address {
+fn parse_addr(s: &str) -> Option<Ipv4Address> {
     let mut parts = s.split('.');
-    let a = parts.next().expect("invalid ip address");
-    let b = parts.next().expect("invalid ip address");
-    let c = parts.next().expect("invalid ip address");
-    let d = parts.next().expect("invalid ip address");
-    let a = a.parse().expect("invalid ip address");
-    let b = b.parse().expect("invalid ip address");
-    let c = c.parse().expect("invalid ip address");
-    let d = d.parse().expect("invalid ip address");
-    Ipv4Address::new(a, b, c, d)
-}
-
-fn parse_cidr(s: &str) -> Ipv4Cidr {
-    let mut parts = s.split('/');
-    let ip = parts.next().expect("invalid cidr address");
-    let port = parts.next().expect("invalid cidr address");
-    let ip = parse_addr(ip);
-    let prefix_len = port.parse().expect("invalid prefix length");
-    Ipv4Cidr::new(ip, prefix_len)
-}
-
-fn parse_endpoint<Ep: AsRef<str>>(s: Ep) -> IpEndpoint {
-    let s = s.as_ref();
-    let mut parts = s.split(':');
-    let ip = parts.next().expect("invalid ip endpoint");
-    let port = parts.next().expect("invalid ip endpoint");
-    let ip = parse_addr(ip);
-    let port = port.parse().expect("invalid port");
-    IpEndpoint::from((ip, port))
+    let mut octets = [0u8; 4];
+    for octet in octets.iter_mut() {
+        *octet = parts.next()?.parse().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Ipv4Address::from(octets))
+}
+
+fn parse_cidr(s: &str) -> Option<Ipv4Cidr> {
+    let (ip, prefix_len) = s.split_once('/')?;
+    let ip = parse_addr(ip)?;
+    let prefix_len = prefix_len.parse().ok().filter(|&len| len <= 32)?;
+    Some(Ipv4Cidr::new(ip, prefix_len))
+}
+
+fn parse_endpoint<Ep: AsRef<str>>(s: Ep) -> Option<IpEndpoint> {
+    let (ip, port) = s.as_ref().split_once(':')?;
+    let ip = parse_addr(ip)?;
+    let port = port.parse().ok()?;
+    Some(IpEndpoint::from((ip, port)))
 }
 
 pub struct ConfigStore {
@@ -294,6 +504,10 @@ pub enum ConfigStoreError {
     RangeTooLarge,
     SerdeError,
     UnknownCommand,
+    // Neither `server` nor `servers` is set
+    NoServer,
+    // A value the firmware can't use, e.g. a malformed address or a zero polling rate
+    InvalidValue,
 }
 
 impl From<serde_json_core::de::Error> for ConfigStoreError {
@@ -348,8 +562,8 @@ impl ConfigStore {
     }
 
     pub fn validate(&self) -> Result<(), ConfigStoreError> {
-        serde_json_core::from_slice::<AppConfig>(json_range(&self.data))?;
-        Ok(())
+        let (config, _) = serde_json_core::from_slice::<AppConfig>(json_range(&self.data))?;
+        config.validate()
     }
 
     pub async fn commit(&mut self) -> Result<(), ConfigStoreError> {
@@ -368,6 +582,37 @@ impl ConfigStore {
         warn!("Config written to flash");
         Ok(())
     }
+
+    /// Serialize the config including the password, to be committed
+    pub fn with_password(config: &AppConfig) -> Result<Self, ConfigStoreError> {
+        let mut ret = Self::new();
+        ret.size = serde_json_core::to_slice(config, &mut ret.data)?;
+        Ok(ret)
+    }
+
+    /// Erase the config sector, the default config is used after reboot
+    pub async fn erase() -> Result<(), ConfigStoreError> {
+        warn!("Erasing config in flash...");
+        let mut flash = FLASH_STORAGE.get().await.write().await;
+        flash.erase(
+            NVS_PARTITION_ADDRESS,
+            NVS_PARTITION_ADDRESS + MAX_CONFIG_SIZE as u32,
+        )?;
+        warn!("Config erased");
+        Ok(())
+    }
+}
+
+/// Erase the config and reboot with the default config.
+pub async fn factory_reset() -> ! {
+    set_indicator_status(IndicatorStatus::FactoryReset).await;
+    if let Err(e) = ConfigStore::erase().await {
+        error!("Failed to erase config: {e:?}");
+    }
+    // Leave the indicator on for a while before rebooting
+    embassy_time::Timer::after(embassy_time::Duration::from_secs(1)).await;
+    warn!("Factory reset done, rebooting...");
+    esp_hal::system::software_reset()
 }
 
 impl Default for ConfigStore {
@@ -104,9 +104,24 @@ cfg_if::cfg_if! {
     }
 }
 
+// Maximum size of the text sent from the host to the server clipboard
 #[cfg(feature = "clipboard")]
 #[env_item]
 pub const MAX_CLIPBOARD_SIZE: usize = 1024;
+// Clipboards received from the server are kept on the heap, this is the total size of the history,
+// the clipboard is truncated if it alone is larger. The clipboard being received takes up to as
+// much again until it replaces the oldest ones
+#[cfg(all(feature = "clipboard", not(feature = "psram")))]
+#[env_item]
+pub const MAX_CLIPBOARD_HEAP_SIZE: usize = 32768;
+// PSRAM has plenty of room
+#[cfg(all(feature = "clipboard", feature = "psram"))]
+#[env_item]
+pub const MAX_CLIPBOARD_HEAP_SIZE: usize = 1048576;
+// Number of recent clipboards kept
+#[cfg(feature = "clipboard")]
+#[env_item]
+pub const CLIPBOARD_HISTORY_SIZE: usize = 4;
 
 // Default config settings
 #[env_item]
@@ -127,6 +142,10 @@ pub const JIGGLE_INTERVAL: u16 = 60;
 pub const POLLING_RATE: u16 = 200;
 #[env_item]
 pub const REVERSED_WHEEL: bool = false;
+// Password of the provisioning access point, at least 8 characters, empty for an open network
+#[cfg(feature = "wifi")]
+#[env_item]
+pub const PROVISIONING_PASSWORD: &str = "";
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "graphics")] {
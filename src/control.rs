@@ -1,15 +1,32 @@
 use embassy_time::{Duration, TimeoutError, with_timeout};
 use embassy_usb_driver::{Endpoint, EndpointError, EndpointIn, EndpointOut};
 use esp_hal::otg_fs::asynch::Driver;
-use log::{info, warn};
+use log::{LevelFilter, info, warn};
 
 use crate::{
-    ConfigStore, RunningState, config::ConfigStoreError, get_running_state,
+    ConfigStore, RunningState,
+    config::{ConfigStoreError, factory_reset},
+    get_running_state,
+    health::{HealthTask, check_in, check_out},
+    logger::{get_log_level, read_log, set_log_level},
+    reset_report::{MAX_LOCATION_LENGTH, MAX_MESSAGE_LENGTH, ResetReport, get_reset_report},
     running_state::get_running_state_mut,
+    set_screen_size,
 };
 
 #[cfg(feature = "ota")]
 use crate::ota::{OtaError, OtaManager};
+#[cfg(feature = "clipboard")]
+use crate::{
+    clipboard::{select_clipboard_slot, set_server_clipboard},
+    constants::MAX_CLIPBOARD_SIZE,
+    request_paste,
+};
+
+// Log bytes sent for each `ReadLog` command
+const LOG_PAGE_SIZE: usize = 1024;
+// A command must be handled within this time, OTA chunks included
+const COMMAND_DEADLINE: Duration = Duration::from_secs(10);
 
 type EpOut = <Driver<'static> as embassy_usb_driver::Driver<'static>>::EndpointOut;
 type EpIn = <Driver<'static> as embassy_usb_driver::Driver<'static>>::EndpointIn;
@@ -19,6 +36,7 @@ enum Error {
     Endpoint,
     Timeout,
     InvalidConfig,
+    InvalidArgument,
     UnknownCommand,
     #[cfg(feature = "ota")]
     Ota(OtaError),
@@ -57,12 +75,38 @@ impl From<Error> for ControlCommandResponse {
 
 #[derive(Debug, Clone, Copy)]
 enum ControlCommand {
-    GetState,
+    /// Get the running state, optional page (1 byte), 0 is the basic state, 1 is the connection
+    /// statistics, 2 is the error history
+    GetState(u8),
     ReadConfig,
     WriteConfig(u8),
     CommitConfig,
     KeepAwake(bool),
     Reboot,
+    /// Erase the config and reboot with the default config
+    FactoryReset,
+    /// Read the log from the offset (4 bytes LE), up to `LOG_PAGE_SIZE` bytes
+    ReadLog(u32),
+    /// Set the log level (1 byte, 0 is off, 5 is trace), only returns the current level if omitted
+    LogLevel(Option<u8>),
+    /// Why the board was reset last time
+    GetResetReport,
+    /// Change the screen size at runtime, width (2 bytes LE) and height (2 bytes LE)
+    SetScreenSize {
+        width: u16,
+        height: u16,
+    },
+    /// Send UTF-8 text to the server clipboard, text length (2 bytes LE), followed by the text in
+    /// 64-byte blocks
+    #[cfg(feature = "clipboard")]
+    SetServerClipboard(u16),
+    /// Select the clipboard history slot (1 byte, 0 is the newest), and start typing it if the
+    /// next byte is not 0
+    #[cfg(feature = "clipboard")]
+    SelectClipboardSlot {
+        slot: u8,
+        paste: bool,
+    },
     /// Start OTA update with total size (4 bytes LE) and CRC32 (4 bytes LE)
     #[cfg(feature = "ota")]
     OtaStart {
@@ -87,12 +131,32 @@ enum ControlCommand {
 impl ControlCommand {
     pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
         match bytes[0] {
-            b's' => Some(Self::GetState),
+            b's' => Some(Self::GetState(bytes.get(1).copied().unwrap_or_default())),
             b'r' => Some(Self::ReadConfig),
             b'w' => Some(Self::WriteConfig(bytes[1])),
             b'c' => Some(Self::CommitConfig),
             b'k' => Some(Self::KeepAwake(bytes[1] != 0)),
             b'b' => Some(Self::Reboot),
+            b'f' => Some(Self::FactoryReset),
+            b'L' if bytes.len() >= 5 => Some(Self::ReadLog(u32::from_le_bytes([
+                bytes[1], bytes[2], bytes[3], bytes[4],
+            ]))),
+            b'V' => Some(Self::LogLevel(bytes.get(1).copied())),
+            b'R' => Some(Self::GetResetReport),
+            b'g' if bytes.len() >= 5 => {
+                let width = u16::from_le_bytes([bytes[1], bytes[2]]);
+                let height = u16::from_le_bytes([bytes[3], bytes[4]]);
+                Some(Self::SetScreenSize { width, height })
+            }
+            #[cfg(feature = "clipboard")]
+            b't' if bytes.len() >= 3 => Some(Self::SetServerClipboard(u16::from_le_bytes([
+                bytes[1], bytes[2],
+            ]))),
+            #[cfg(feature = "clipboard")]
+            b'l' if bytes.len() >= 2 => Some(Self::SelectClipboardSlot {
+                slot: bytes[1],
+                paste: bytes.get(2).is_some_and(|b| *b != 0),
+            }),
             #[cfg(feature = "ota")]
             b'O' if bytes.len() >= 9 => {
                 // OtaStart: 'O' + size (4 bytes LE) + crc (4 bytes LE)
@@ -118,7 +182,20 @@ impl ControlCommand {
 
 enum ControlCommandResponse {
     State(RunningState),
+    Stats(RunningState),
+    ErrorHistory(RunningState),
     Config(u8),
+    /// Log page: offset (4 bytes LE), length (2 bytes LE) and the end of the log (4 bytes LE),
+    /// followed by the data in 64-byte blocks
+    LogPage {
+        offset: u32,
+        len: u16,
+        end: u32,
+    },
+    LogLevel(u8),
+    /// The last reset, see `ResetReport::to_bytes`, followed by the location and the message in
+    /// 64-byte blocks
+    ResetReport(&'static ResetReport),
     Ok,
     Error(Error),
     /// OTA progress: received bytes (4 bytes LE), total bytes (4 bytes LE)
@@ -140,11 +217,38 @@ impl ControlCommandResponse {
                 let len = state.to_bytes(&mut bytes[1..]).len();
                 &bytes[..len + 1]
             }
+            Self::Stats(state) => {
+                bytes[0] = b's';
+                let len = state.stats.to_bytes(&mut bytes[1..]).len();
+                &bytes[..len + 1]
+            }
+            Self::ErrorHistory(state) => {
+                bytes[0] = b's';
+                let len = state.stats.errors_to_bytes(&mut bytes[1..]).len();
+                &bytes[..len + 1]
+            }
             Self::Config(value) => {
                 bytes[0] = b'r';
                 bytes[1] = *value;
                 &bytes[..2]
             }
+            Self::LogPage { offset, len, end } => {
+                bytes[0] = b'L';
+                bytes[1..5].copy_from_slice(&offset.to_le_bytes());
+                bytes[5..7].copy_from_slice(&len.to_le_bytes());
+                bytes[7..11].copy_from_slice(&end.to_le_bytes());
+                &bytes[..11]
+            }
+            Self::LogLevel(level) => {
+                bytes[0] = b'V';
+                bytes[1] = *level;
+                &bytes[..2]
+            }
+            Self::ResetReport(report) => {
+                bytes[0] = b'R';
+                let len = report.to_bytes(&mut bytes[1..]).len();
+                &bytes[..len + 1]
+            }
             Self::Ok => {
                 bytes[0] = b'o';
                 &bytes[..1]
@@ -155,6 +259,7 @@ impl ControlCommandResponse {
                     Error::Endpoint => bytes[1] = b'e',
                     Error::Timeout => bytes[1] = b't',
                     Error::InvalidConfig => bytes[1] = b'i',
+                    Error::InvalidArgument => bytes[1] = b'a',
                     Error::UnknownCommand => bytes[1] = b'u',
                     #[cfg(feature = "ota")]
                     Error::Ota(ota_err) => {
@@ -201,19 +306,22 @@ pub async fn control_task(mut read_ep: EpOut, mut write_ep: EpIn) {
         let mut ota_manager = OtaManager::new();
 
         while let Ok(n) = read_ep.read(&mut data).await {
+            check_in(HealthTask::Control, COMMAND_DEADLINE);
             let cmd = ControlCommand::from_bytes(&data[0..n]);
             info!("Got command: {cmd:?}");
             match cmd {
-                Some(ControlCommand::GetState) => {
-                    write_response(
-                        &mut write_ep,
-                        ControlCommandResponse::State(get_running_state().await),
-                    )
-                    .await
-                    .ok();
+                Some(ControlCommand::GetState(page)) => {
+                    let state = get_running_state().await;
+                    let response = match page {
+                        0 => ControlCommandResponse::State(state),
+                        1 => ControlCommandResponse::Stats(state),
+                        2 => ControlCommandResponse::ErrorHistory(state),
+                        _ => Error::InvalidArgument.into(),
+                    };
+                    write_response(&mut write_ep, response).await.ok();
                 }
                 Some(ControlCommand::KeepAwake(keep_awake)) => {
-                    get_running_state_mut().await.keep_awake = keep_awake;
+                    set_keep_awake(keep_awake).await;
                     write_response(&mut write_ep, ControlCommandResponse::Ok)
                         .await
                         .ok();
@@ -262,13 +370,83 @@ pub async fn control_task(mut read_ep: EpOut, mut write_ep: EpIn) {
                     }
                 },
                 Some(ControlCommand::Reboot) => {
+                    write_response(&mut write_ep, ControlCommandResponse::Ok)
+                        .await
+                        .ok();
+                    reboot().await
+                }
+                Some(ControlCommand::FactoryReset) => {
                     write_response(&mut write_ep, ControlCommandResponse::Ok)
                         .await
                         .ok();
                     // Wait for a short while to send the Ok response back
                     embassy_time::Timer::after(Duration::from_millis(100)).await;
-                    info!("Rebooting...");
-                    esp_hal::system::software_reset()
+                    factory_reset().await
+                }
+                Some(ControlCommand::ReadLog(offset)) => {
+                    send_log(&mut write_ep, offset).await.ok();
+                }
+                Some(ControlCommand::LogLevel(level)) => {
+                    let response = match level.map(|l| LevelFilter::iter().nth(l as usize)) {
+                        Some(Some(level)) => {
+                            ControlCommandResponse::LogLevel(set_log_level(level) as u8)
+                        }
+                        Some(None) => Error::InvalidArgument.into(),
+                        None => ControlCommandResponse::LogLevel(get_log_level() as u8),
+                    };
+                    write_response(&mut write_ep, response).await.ok();
+                }
+                Some(ControlCommand::GetResetReport) => match get_reset_report() {
+                    Some(report) => {
+                        send_reset_report(&mut write_ep, report).await.ok();
+                    }
+                    None => {
+                        write_response(&mut write_ep, Error::InvalidArgument.into())
+                            .await
+                            .ok();
+                    }
+                },
+                Some(ControlCommand::SetScreenSize { width, height }) => {
+                    if width == 0 || height == 0 {
+                        write_response(&mut write_ep, Error::InvalidArgument.into())
+                            .await
+                            .ok();
+                    } else {
+                        set_screen_size(width, height);
+                        write_response(&mut write_ep, ControlCommandResponse::Ok)
+                            .await
+                            .ok();
+                    }
+                }
+                #[cfg(feature = "clipboard")]
+                Some(ControlCommand::SetServerClipboard(length)) => {
+                    match receive_text(&mut read_ep, length as usize).await {
+                        Ok(text) => {
+                            set_server_clipboard(text);
+                            write_response(&mut write_ep, ControlCommandResponse::Ok)
+                                .await
+                                .ok();
+                        }
+                        Err(e) => {
+                            warn!("Invalid clipboard text received");
+                            write_response(&mut write_ep, e.into()).await.ok();
+                        }
+                    }
+                }
+                #[cfg(feature = "clipboard")]
+                Some(ControlCommand::SelectClipboardSlot { slot, paste }) => {
+                    if select_clipboard_slot(slot as usize).await {
+                        if paste {
+                            request_paste();
+                        }
+                        write_response(&mut write_ep, ControlCommandResponse::Ok)
+                            .await
+                            .ok();
+                    } else {
+                        write_response(&mut write_ep, Error::InvalidArgument.into())
+                            .await
+                            .ok();
+                    }
                 }
                 #[cfg(feature = "ota")]
                 Some(ControlCommand::OtaStart { size, crc }) => {
@@ -370,6 +548,8 @@ pub async fn control_task(mut read_ep: EpOut, mut write_ep: EpIn) {
                         .ok();
                 }
             }
+            // Waiting for the next command
+            check_out(HealthTask::Control);
         }
         info!("Control interface disconnected");
         #[cfg(feature = "ota")]
@@ -383,6 +563,18 @@ pub async fn control_task(mut read_ep: EpOut, mut write_ep: EpIn) {
     }
 }
 
+pub(crate) async fn set_keep_awake(keep_awake: bool) {
+    info!("Keep awake: {keep_awake}");
+    get_running_state_mut().await.keep_awake = keep_awake;
+}
+
+/// Reboot after a short while, so the response can be sent back first
+pub(crate) async fn reboot() -> ! {
+    embassy_time::Timer::after(Duration::from_millis(100)).await;
+    info!("Rebooting...");
+    esp_hal::system::software_reset()
+}
+
 async fn write_response(
     write_ep: &mut EpIn,
     response: ControlCommandResponse,
@@ -418,6 +610,44 @@ async fn send_config(write_ep: &mut EpIn) -> Result<(), Error> {
     .await?
 }
 
+async fn send_log(write_ep: &mut EpIn, offset: u32) -> Result<(), Error> {
+    let mut page = [0; LOG_PAGE_SIZE];
+    let (offset, len, end) = read_log(offset, &mut page);
+    with_timeout(Duration::from_millis(1000), async {
+        let len = len as u16;
+        write_response(
+            write_ep,
+            ControlCommandResponse::LogPage { offset, len, end },
+        )
+        .await?;
+        for block in page[..len as usize].chunks(64) {
+            write_ep
+                .write(block)
+                .await
+                .map_err(|_| EndpointError::Disabled)?;
+        }
+        Result::<(), Error>::Ok(())
+    })
+    .await?
+}
+
+async fn send_reset_report(write_ep: &mut EpIn, report: &'static ResetReport) -> Result<(), Error> {
+    let mut text = heapless::Vec::<u8, { MAX_LOCATION_LENGTH + MAX_MESSAGE_LENGTH }>::new();
+    text.extend_from_slice(report.location.as_bytes()).ok();
+    text.extend_from_slice(report.message.as_bytes()).ok();
+    with_timeout(Duration::from_millis(1000), async {
+        write_response(write_ep, ControlCommandResponse::ResetReport(report)).await?;
+        for block in text.chunks(64) {
+            write_ep
+                .write(block)
+                .await
+                .map_err(|_| EndpointError::Disabled)?;
+        }
+        Result::<(), Error>::Ok(())
+    })
+    .await?
+}
+
 async fn receive_config(read_ep: &mut EpOut, blocks: usize) -> Result<ConfigStore, Error> {
     with_timeout(Duration::from_millis(1000), async {
         let mut store = ConfigStore::new();
@@ -437,6 +667,37 @@ async fn receive_config(read_ep: &mut EpOut, blocks: usize) -> Result<ConfigStor
     .await?
 }
 
+/// Receive `length` bytes of UTF-8 text in 64-byte blocks.
+#[cfg(feature = "clipboard")]
+async fn receive_text(
+    read_ep: &mut EpOut,
+    length: usize,
+) -> Result<heapless::Vec<u8, MAX_CLIPBOARD_SIZE>, Error> {
+    if length > MAX_CLIPBOARD_SIZE {
+        // Drain the blocks so they are not taken as commands
+        let mut data = [0; 64];
+        for _ in 0..length.div_ceil(64) {
+            with_timeout(Duration::from_millis(1000), read_ep.read(&mut data)).await??;
+        }
+        return Err(Error::InvalidArgument);
+    }
+    let text = with_timeout(Duration::from_millis(1000), async {
+        let mut text = heapless::Vec::<u8, MAX_CLIPBOARD_SIZE>::new();
+        let mut data = [0; 64];
+        while text.len() < length {
+            let n = read_ep.read(&mut data).await?;
+            let n = n.min(length - text.len());
+            text.extend_from_slice(&data[..n]).ok();
+        }
+        Result::<_, Error>::Ok(text)
+    })
+    .await??;
+    if core::str::from_utf8(&text).is_err() {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(text)
+}
+
 /// Receive OTA data chunk from USB and write to flash.
 ///
 /// This function receives `packets` number of 64-byte USB packets (up to 4096 bytes total),
@@ -0,0 +1,312 @@
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_LONG_PRESS_TIME: u16 = 700;
+const DEFAULT_BOOT_HOLD_TIME: u16 = 3000;
+
+/// Button gestures recognized by `GestureRecognizer`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    Short,
+    Long,
+    Double,
+    // The button was pressed at power-up and held long enough
+    BootHold,
+}
+
+/// What to do when a button gesture is recognized
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonAction {
+    #[default]
+    None,
+    Paste,
+    CancelPaste,
+    ToggleKeepAwake,
+    CycleClipboardSlot,
+    Reboot,
+    Provisioning,
+    FactoryReset,
+}
+
+/// Button gesture timings in milliseconds, and the action of each gesture.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ButtonConfig {
+    #[serde(default = "get_default_short_press")]
+    pub short_press: ButtonAction,
+    #[serde(default = "get_default_long_press")]
+    pub long_press: ButtonAction,
+    #[serde(default)]
+    pub double_press: ButtonAction,
+    #[serde(default = "get_default_boot_hold")]
+    pub boot_hold: ButtonAction,
+    // Holding the button longer than this is a long press
+    #[serde(default = "get_default_long_press_time")]
+    pub long_press_time: u16,
+    // A press within this time after a short press makes a double press, 0 disables double press,
+    // short presses are reported this much later
+    #[serde(default)]
+    pub double_press_time: u16,
+    // Holding the button this long since power-up triggers the boot hold action
+    #[serde(default = "get_default_boot_hold_time")]
+    pub boot_hold_time: u16,
+}
+
+fn get_default_short_press() -> ButtonAction {
+    ButtonAction::Paste
+}
+
+fn get_default_long_press() -> ButtonAction {
+    ButtonAction::CycleClipboardSlot
+}
+
+fn get_default_boot_hold() -> ButtonAction {
+    ButtonAction::FactoryReset
+}
+
+fn get_default_long_press_time() -> u16 {
+    DEFAULT_LONG_PRESS_TIME
+}
+
+fn get_default_boot_hold_time() -> u16 {
+    DEFAULT_BOOT_HOLD_TIME
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        Self {
+            short_press: get_default_short_press(),
+            long_press: get_default_long_press(),
+            double_press: ButtonAction::None,
+            boot_hold: get_default_boot_hold(),
+            long_press_time: DEFAULT_LONG_PRESS_TIME,
+            double_press_time: 0,
+            boot_hold_time: DEFAULT_BOOT_HOLD_TIME,
+        }
+    }
+}
+
+impl ButtonConfig {
+    pub fn action(&self, gesture: Gesture) -> ButtonAction {
+        match gesture {
+            Gesture::Short => self.short_press,
+            Gesture::Long => self.long_press,
+            Gesture::Double => self.double_press,
+            Gesture::BootHold => self.boot_hold,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Idle,
+    // `second` is set for the 2nd press of a double press
+    Pressed { since: u64, second: bool },
+    // Released after a short press, waiting for the 2nd press of a double press
+    Released { at: u64 },
+    // The gesture of this press has been reported, waiting for the release
+    Held,
+    // Pressed since power-up
+    BootPressed { since: u64 },
+}
+
+/// Turns debounced button edges into gestures.
+///
+/// All times are in milliseconds. The caller feeds the edges with `press` and `release`, and calls
+/// `poll` once the `deadline` has passed, as long and short presses are reported without an edge.
+pub struct GestureRecognizer {
+    long_press_time: u64,
+    double_press_time: u64,
+    boot_hold_time: u64,
+    state: State,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: &ButtonConfig, pressed_at_boot: bool, now: u64) -> Self {
+        Self {
+            long_press_time: config.long_press_time as u64,
+            double_press_time: config.double_press_time as u64,
+            boot_hold_time: config.boot_hold_time as u64,
+            state: if pressed_at_boot {
+                State::BootPressed { since: now }
+            } else {
+                State::Idle
+            },
+        }
+    }
+
+    pub fn press(&mut self, now: u64) -> Option<Gesture> {
+        match self.state {
+            State::Idle => {
+                self.state = State::Pressed {
+                    since: now,
+                    second: false,
+                };
+                None
+            }
+            State::Released { at } => {
+                if now < at + self.double_press_time {
+                    self.state = State::Pressed {
+                        since: now,
+                        second: true,
+                    };
+                    None
+                } else {
+                    // `poll` was late, the previous press was a short one
+                    self.state = State::Pressed {
+                        since: now,
+                        second: false,
+                    };
+                    Some(Gesture::Short)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn release(&mut self, now: u64) -> Option<Gesture> {
+        let gesture = match self.state {
+            State::Pressed { since, .. } if now >= since + self.long_press_time => {
+                Some(Gesture::Long)
+            }
+            State::Pressed { second: true, .. } => Some(Gesture::Double),
+            State::Pressed { .. } if self.double_press_time > 0 => {
+                self.state = State::Released { at: now };
+                return None;
+            }
+            State::Pressed { .. } => Some(Gesture::Short),
+            State::BootPressed { since } if now >= since + self.boot_hold_time => {
+                Some(Gesture::BootHold)
+            }
+            // Released too early at boot, or after a reported gesture
+            _ => None,
+        };
+        self.state = State::Idle;
+        gesture
+    }
+
+    /// Time when `poll` should be called if no edge comes before it
+    pub fn deadline(&self) -> Option<u64> {
+        match self.state {
+            State::Pressed { since, .. } => Some(since + self.long_press_time),
+            State::Released { at } => Some(at + self.double_press_time),
+            State::BootPressed { since } => Some(since + self.boot_hold_time),
+            State::Idle | State::Held => None,
+        }
+    }
+
+    pub fn poll(&mut self, now: u64) -> Option<Gesture> {
+        if self.deadline().is_none_or(|deadline| now < deadline) {
+            return None;
+        }
+        let gesture = match self.state {
+            State::Pressed { .. } => {
+                self.state = State::Held;
+                Gesture::Long
+            }
+            State::Released { .. } => {
+                self.state = State::Idle;
+                Gesture::Short
+            }
+            State::BootPressed { .. } => {
+                self.state = State::Held;
+                Gesture::BootHold
+            }
+            State::Idle | State::Held => return None,
+        };
+        Some(gesture)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ButtonConfig, Gesture, GestureRecognizer};
+
+    fn config(double_press_time: u16) -> ButtonConfig {
+        ButtonConfig {
+            double_press_time,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_short_press() {
+        let mut g = GestureRecognizer::new(&config(0), false, 0);
+        assert_eq!(g.deadline(), None);
+        assert_eq!(g.press(1000), None);
+        assert_eq!(g.deadline(), Some(1700));
+        assert_eq!(g.poll(1500), None);
+        assert_eq!(g.release(1600), Some(Gesture::Short));
+        assert_eq!(g.deadline(), None);
+    }
+
+    #[test]
+    fn test_long_press() {
+        let mut g = GestureRecognizer::new(&config(0), false, 0);
+        assert_eq!(g.press(1000), None);
+        assert_eq!(g.poll(1700), Some(Gesture::Long));
+        assert_eq!(g.poll(5000), None);
+        // Reported only once
+        assert_eq!(g.release(5000), None);
+        // Released after the deadline without polling
+        assert_eq!(g.press(6000), None);
+        assert_eq!(g.release(6800), Some(Gesture::Long));
+    }
+
+    #[test]
+    fn test_double_press() {
+        let mut g = GestureRecognizer::new(&config(300), false, 0);
+        assert_eq!(g.press(1000), None);
+        assert_eq!(g.release(1100), None);
+        assert_eq!(g.deadline(), Some(1400));
+        assert_eq!(g.press(1300), None);
+        assert_eq!(g.release(1400), Some(Gesture::Double));
+        assert_eq!(g.poll(2000), None);
+    }
+
+    #[test]
+    fn test_short_press_with_double_press_enabled() {
+        let mut g = GestureRecognizer::new(&config(300), false, 0);
+        assert_eq!(g.press(1000), None);
+        assert_eq!(g.release(1100), None);
+        assert_eq!(g.poll(1399), None);
+        assert_eq!(g.poll(1400), Some(Gesture::Short));
+        assert_eq!(g.deadline(), None);
+
+        // The short press is reported on the next press if the deadline was missed
+        assert_eq!(g.press(2000), None);
+        assert_eq!(g.release(2100), None);
+        assert_eq!(g.press(2500), Some(Gesture::Short));
+        assert_eq!(g.release(2600), None);
+        assert_eq!(g.poll(2900), Some(Gesture::Short));
+    }
+
+    #[test]
+    fn test_second_press_held_long() {
+        let mut g = GestureRecognizer::new(&config(300), false, 0);
+        assert_eq!(g.press(1000), None);
+        assert_eq!(g.release(1100), None);
+        assert_eq!(g.press(1200), None);
+        assert_eq!(g.poll(1900), Some(Gesture::Long));
+        assert_eq!(g.release(2000), None);
+    }
+
+    #[test]
+    fn test_boot_hold() {
+        let mut g = GestureRecognizer::new(&config(0), true, 100);
+        assert_eq!(g.deadline(), Some(3100));
+        assert_eq!(g.press(200), None);
+        assert_eq!(g.poll(3100), Some(Gesture::BootHold));
+        assert_eq!(g.release(4000), None);
+        assert_eq!(g.press(5000), None);
+        assert_eq!(g.release(5100), Some(Gesture::Short));
+
+        // Released too early
+        let mut g = GestureRecognizer::new(&config(0), true, 100);
+        assert_eq!(g.release(1000), None);
+        assert_eq!(g.poll(5000), None);
+
+        // Released after the deadline without polling
+        let mut g = GestureRecognizer::new(&config(0), true, 100);
+        assert_eq!(g.release(3200), Some(Gesture::BootHold));
+    }
+}
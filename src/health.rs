@@ -0,0 +1,83 @@
+//! Liveness of the tasks the board can't work without.
+//!
+//! Each task checks in with the time it must check in again by, and checks out before waiting for
+//! something that may never come, e.g. the next HID report. The watchdog task only feeds the
+//! watchdog while no task is overdue, so a hung task resets the board instead of leaving it dead.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use embassy_time::{Duration, Instant};
+use log::error;
+
+use crate::reset_report::{ResetCause, record_reset_cause};
+
+const NOT_WATCHED: u32 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthTask {
+    HidWriter = 0,
+    Client = 1,
+    Network = 2,
+    Control = 3,
+}
+
+impl HealthTask {
+    const ALL: [HealthTask; 4] = [
+        HealthTask::HidWriter,
+        HealthTask::Client,
+        HealthTask::Network,
+        HealthTask::Control,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HealthTask::HidWriter => "hid_writer",
+            HealthTask::Client => "client",
+            HealthTask::Network => "network",
+            HealthTask::Control => "control",
+        }
+    }
+}
+
+// Milliseconds since boot each task must check in by, wraps after 49 days
+static DEADLINES: [AtomicU32; 4] = [const { AtomicU32::new(NOT_WATCHED) }; 4];
+// Set once a stuck task is recorded, the watchdog resets the board soon after
+static STUCK: AtomicBool = AtomicBool::new(false);
+
+fn now_ms() -> u32 {
+    Instant::now().as_millis() as u32
+}
+
+/// The task is alive, and checks in again within `deadline`
+pub fn check_in(task: HealthTask, deadline: Duration) {
+    let deadline = now_ms().wrapping_add(deadline.as_millis() as u32);
+    DEADLINES[task as usize].store(deadline.max(1), Ordering::Relaxed);
+}
+
+/// The task is waiting for something that can take forever, it's not watched until it checks in
+/// again
+pub fn check_out(task: HealthTask) {
+    DEADLINES[task as usize].store(NOT_WATCHED, Ordering::Relaxed);
+}
+
+/// Returns `false` if a task missed its deadline, the stuck task is recorded for the reset report
+pub fn check_health() -> bool {
+    if STUCK.load(Ordering::Relaxed) {
+        return false;
+    }
+    let now = now_ms();
+    let stuck = HealthTask::ALL.into_iter().find(|task| {
+        let deadline = DEADLINES[*task as usize].load(Ordering::Relaxed);
+        // Also right across the wrap-around
+        deadline != NOT_WATCHED && (now.wrapping_sub(deadline) as i32) > 0
+    });
+    match stuck {
+        Some(task) => {
+            error!("Task '{}' is stuck, resetting the system.", task.name());
+            record_reset_cause(ResetCause::TaskStuck, task.name());
+            STUCK.store(true, Ordering::Relaxed);
+            false
+        }
+        None => true,
+    }
+}
@@ -11,17 +11,21 @@ use embassy_sync::{
 };
 use embassy_time::{Duration, with_timeout};
 use embassy_usb::{
+    class::cdc_acm::{CdcAcmClass, State as CdcAcmState},
     class::hid::HidWriter,
     class::web_usb::{Config as WebUsbConfig, State as WebUsbState, Url as WebUsbUrl},
     msos::{self, windows_version},
 };
-use esp_hal::{
-    otg_fs::{Usb, asynch::Driver},
-    system,
-};
+use esp_hal::otg_fs::{Usb, asynch::Driver};
 use log::{debug, info, warn};
 
-use crate::{AppConfig, SynergyHid, constants::DEVICE_INTERFACE_GUIDS, mk_static};
+use crate::{
+    AppConfig, SynergyHid,
+    constants::DEVICE_INTERFACE_GUIDS,
+    health::{HealthTask, check_in, check_out},
+    mk_static,
+    reset_report::{ResetCause, reset},
+};
 
 type ReportWriter<'a, const N: usize> = HidWriter<'a, Driver<'a>, N>;
 
@@ -30,6 +34,7 @@ pub enum HidReport {
     Keyboard([u8; 9]),
     Mouse([u8; 8]),
     Consumer([u8; 3]),
+    AppleVendor([u8; 2]),
 }
 
 impl HidReport {
@@ -53,6 +58,9 @@ impl HidReport {
 pub type HidReportSender = Sender<'static, CriticalSectionRawMutex, HidReport, 32>;
 
 type HidReportChannel = Channel<CriticalSectionRawMutex, HidReport, 32>;
+// Writing a report times out way before this
+const WRITE_DEADLINE: Duration = Duration::from_secs(1);
+
 type HidReportReceiver = Receiver<'static, CriticalSectionRawMutex, HidReport, 32>;
 
 trait HidReportWriter {
@@ -81,6 +89,7 @@ impl HidReportWriter for UsbHidReportWriter<'_> {
             HidReport::Keyboard(data) => data,
             HidReport::Mouse(data) => data,
             HidReport::Consumer(data) => data,
+            HidReport::AppleVendor(data) => data,
         };
         // Assuming 10 * polling_interval is enough time for the host to poll the device, but not too short or too long.
         let timeout = Duration::from_millis((self.polling_interval as u64 * 10).clamp(100, 200));
@@ -108,11 +117,10 @@ impl HidReportWriter for UsbHidReportWriter<'_> {
             // Above scenario may happen if the device is plugged into a USB hub which
             // supplies power to the device even if the host is disconnected or powered
             // off.
-            // There is no way we can resume the USB stack, so we just panic here, and
-            // the watchdog will reset the board.
+            // There is no way we can resume the USB stack, so we just reset the board.
             // @see https://docs.espressif.com/projects/esp-idf/zh_CN/latest/esp32s3/api-reference/peripherals/usb_device.html#self-powered-device
             warn!("Timeout writing HID report, resetting the system.");
-            system::software_reset()
+            reset(ResetCause::HidTimeout, "Timeout writing HID report")
         }
     }
 }
@@ -121,7 +129,10 @@ impl HidReportWriter for UsbHidReportWriter<'_> {
 async fn start_hid_report_writer(writer: ReportWriter<'static, 9>, receiver: HidReportReceiver) {
     let mut writer = UsbHidReportWriter::new(writer);
     loop {
+        // Nothing to do until the next report
+        check_out(HealthTask::HidWriter);
         let report = receiver.receive().await;
+        check_in(HealthTask::HidWriter, WRITE_DEADLINE);
 
         // Skip sending HID reports during OTA to prevent timeout panics.
         // Flash writes are blocking and can take significant time, which would
@@ -230,7 +241,7 @@ pub fn start_hid_task(spawner: Spawner, usb: Usb<'static>) {
 
     // Create classes on the builder.
     let config = embassy_usb::class::hid::Config {
-        report_descriptor: SynergyHid::get_report_descriptor().1,
+        report_descriptor: SynergyHid::get_report_descriptor(app_config.apple_fn_key.is_some()).1,
         request_handler: None,
         poll_ms: app_config.get_polling_interval(),
         max_packet_size: 64,
@@ -275,6 +286,12 @@ pub fn start_hid_task(spawner: Spawner, usb: Usb<'static>) {
     drop(function);
     spawner.must_spawn(crate::control::control_task(read_ep, write_ep));
 
+    // Add a CDC-ACM serial port for the console and Improv WiFi provisioning
+    // There are not enough IN endpoints for another serial port, so both share this one
+    let cdc_acm_state = mk_static!(CdcAcmState<'static>, CdcAcmState::new());
+    let serial = CdcAcmClass::new(&mut builder, cdc_acm_state, 64);
+    spawner.must_spawn(crate::serial::serial_task(serial));
+
     // // Run the USB device.
     spawner.must_spawn(usb_task(builder));
 
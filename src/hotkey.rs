@@ -0,0 +1,137 @@
+use heapless::Vec;
+
+/// Maximum number of keys held at the same time that are tracked
+const MAX_PRESSED_KEYS: usize = 8;
+
+/// Detects configured key chords in the key stream sent by the server.
+///
+/// Chords are lists of Synergy key codes, a chord matches when its last key is pressed while all
+/// the other keys are held. Letters are matched case-insensitively as Shift changes the key code.
+#[derive(Default)]
+pub struct HotkeyTracker {
+    // Keys currently held on the server
+    pressed: Vec<u16, MAX_PRESSED_KEYS>,
+    // Keys of a matched chord, their key up events are not forwarded to the host
+    suppressed: Vec<u16, MAX_PRESSED_KEYS>,
+}
+
+impl HotkeyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track a key down, returns the index of the chord in `chords` it completes, if any.
+    pub fn key_down(&mut self, key: u16, chords: &[&[u16]]) -> Option<usize> {
+        let key = normalize(key);
+        if !self.pressed.contains(&key) {
+            self.pressed.push(key).ok();
+        }
+        let index = chords.iter().position(|chord| {
+            !chord.is_empty()
+                && chord.iter().any(|k| normalize(*k) == key)
+                && chord.iter().all(|k| self.pressed.contains(&normalize(*k)))
+        })?;
+        // All held keys are released on the host when the chord fires
+        self.suppressed = self.pressed.clone();
+        Some(index)
+    }
+
+    /// Track a key up, returns `true` if the key up should not be forwarded to the host.
+    pub fn key_up(&mut self, key: u16) -> bool {
+        let key = normalize(key);
+        self.pressed.retain(|k| *k != key);
+        match self.suppressed.iter().position(|k| *k == key) {
+            Some(pos) => {
+                self.suppressed.swap_remove(pos);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pressed.clear();
+        self.suppressed.clear();
+    }
+}
+
+fn normalize(key: u16) -> u16 {
+    match key {
+        0x41..=0x5A => key + 0x20,
+        _ => key,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HotkeyTracker;
+
+    // kKeyControl_L, kKeyShift_L
+    const CTRL: u16 = 0xEFE3;
+    const SHIFT: u16 = 0xEFE1;
+
+    #[test]
+    fn test_chord_match() {
+        let mut hotkeys = HotkeyTracker::new();
+        let paste: &[u16] = &[CTRL, SHIFT, 'v' as u16];
+        let cancel: &[u16] = &[CTRL, SHIFT, 'c' as u16];
+        assert_eq!(hotkeys.key_down(CTRL, &[paste, cancel]), None);
+        assert_eq!(hotkeys.key_down(SHIFT, &[paste, cancel]), None);
+        // Shift makes the server send the upper case letter
+        assert_eq!(hotkeys.key_down('C' as u16, &[paste, cancel]), Some(1));
+        // Any order completes the chord, empty chords never match
+        let mut hotkeys = HotkeyTracker::new();
+        assert_eq!(hotkeys.key_down('v' as u16, &[&[], paste]), None);
+        assert_eq!(hotkeys.key_down(SHIFT, &[&[], paste]), None);
+        assert_eq!(hotkeys.key_down(CTRL, &[&[], paste]), Some(1));
+    }
+
+    #[test]
+    fn test_partial_match() {
+        let mut hotkeys = HotkeyTracker::new();
+        let paste: &[u16] = &[CTRL, SHIFT, 'v' as u16];
+        assert_eq!(hotkeys.key_down(CTRL, &[paste]), None);
+        assert_eq!(hotkeys.key_down('v' as u16, &[paste]), None);
+        // Keys of a chord that didn't match are forwarded
+        assert!(!hotkeys.key_up('v' as u16));
+        assert!(!hotkeys.key_up(CTRL));
+        // A released key no longer counts
+        assert_eq!(hotkeys.key_down(SHIFT, &[paste]), None);
+        assert_eq!(hotkeys.key_down(CTRL, &[paste]), None);
+    }
+
+    #[test]
+    fn test_release_order() {
+        let mut hotkeys = HotkeyTracker::new();
+        let paste: &[u16] = &[CTRL, 'v' as u16];
+        // A key held before the chord is suppressed as well
+        assert_eq!(hotkeys.key_down('a' as u16, &[paste]), None);
+        assert_eq!(hotkeys.key_down(CTRL, &[paste]), None);
+        assert_eq!(hotkeys.key_down('V' as u16, &[paste]), Some(0));
+        // Each key up is suppressed once, in any order
+        assert!(hotkeys.key_up(CTRL));
+        assert!(hotkeys.key_up('a' as u16));
+        assert!(!hotkeys.key_up(CTRL));
+        assert!(hotkeys.key_up('v' as u16));
+        assert!(!hotkeys.key_up('v' as u16));
+        // Keys pressed after the chord are forwarded
+        assert_eq!(hotkeys.key_down('b' as u16, &[paste]), None);
+        assert!(!hotkeys.key_up('b' as u16));
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut hotkeys = HotkeyTracker::new();
+        let paste: &[u16] = &[CTRL, 'v' as u16];
+        hotkeys.key_down(CTRL, &[paste]);
+        hotkeys.key_down('v' as u16, &[paste]);
+        // E.g. the screen is left, the key ups are forwarded again
+        hotkeys.clear();
+        assert!(!hotkeys.key_up(CTRL));
+        assert!(!hotkeys.key_up('v' as u16));
+        // The keys held before clearing don't count
+        hotkeys.key_down(CTRL, &[paste]);
+        hotkeys.clear();
+        assert_eq!(hotkeys.key_down('v' as u16, &[paste]), None);
+    }
+}
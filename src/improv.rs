@@ -0,0 +1,293 @@
+//! Improv Serial protocol, used by Improv tools and ESP Web Tools to set the WiFi up.
+//!
+//! Each packet is `IMPROV`, the version, the type, the data length, the data and the checksum,
+//! which is the sum of all previous bytes. Other bytes on the serial port are passed through.
+//! @see https://www.improv-wifi.com/serial/
+
+const HEADER: &[u8; 6] = b"IMPROV";
+const VERSION: u8 = 1;
+// Header, version, type and length
+const PREFIX_LEN: usize = HEADER.len() + 3;
+const MAX_PACKET_LEN: usize = PREFIX_LEN + u8::MAX as usize + 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PacketType {
+    CurrentState = 0x01,
+    ErrorState = 0x02,
+    Rpc = 0x03,
+    RpcResult = 0x04,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ImprovState {
+    Ready = 0x02,
+    Provisioning = 0x03,
+    Provisioned = 0x04,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ImprovError {
+    InvalidRpc = 0x01,
+    UnknownRpc = 0x02,
+    UnableToConnect = 0x03,
+    Unknown = 0xFF,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    WifiSettings { ssid: &'a str, password: &'a str },
+    GetCurrentState,
+    GetDeviceInfo,
+    GetWifiNetworks,
+}
+
+impl Command<'_> {
+    pub fn id(&self) -> u8 {
+        match self {
+            Command::WifiSettings { .. } => 0x01,
+            Command::GetCurrentState => 0x02,
+            Command::GetDeviceInfo => 0x03,
+            Command::GetWifiNetworks => 0x04,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Feed<'a> {
+    // The byte may be part of a packet
+    Pending,
+    // A complete packet with valid checksum, the type and the data
+    Packet(u8, &'a [u8]),
+    // A complete packet with wrong checksum or version
+    Invalid,
+    // Bytes that turned out not to be a packet
+    Passthrough(heapless::Vec<u8, { HEADER.len() + 1 }>),
+}
+
+/// Picks Improv packets out of the serial byte stream
+#[derive(Default)]
+pub struct ImprovParser {
+    buf: heapless::Vec<u8, MAX_PACKET_LEN>,
+    // The packet in `buf` has been returned, clear it on the next byte
+    done: bool,
+}
+
+impl ImprovParser {
+    pub fn feed(&mut self, byte: u8) -> Feed<'_> {
+        if self.done {
+            self.buf.clear();
+            self.done = false;
+        }
+        let len = self.buf.len();
+        if len < HEADER.len() {
+            if byte == HEADER[len] {
+                self.buf.push(byte).ok();
+                return Feed::Pending;
+            }
+            let mut passthrough = heapless::Vec::new();
+            passthrough.extend_from_slice(&self.buf).ok();
+            self.buf.clear();
+            if byte == HEADER[0] {
+                // A new packet may start here
+                self.buf.push(byte).ok();
+            } else {
+                passthrough.push(byte).ok();
+            }
+            return if passthrough.is_empty() {
+                Feed::Pending
+            } else {
+                Feed::Passthrough(passthrough)
+            };
+        }
+        self.buf.push(byte).ok();
+        if self.buf.len() < PREFIX_LEN || self.buf.len() < PREFIX_LEN + self.buf[8] as usize + 1 {
+            return Feed::Pending;
+        }
+        self.done = true;
+        let (packet, sum) = self.buf.split_at(self.buf.len() - 1);
+        if packet[6] != VERSION || checksum(packet) != sum[0] {
+            return Feed::Invalid;
+        }
+        Feed::Packet(packet[7], &packet[PREFIX_LEN..])
+    }
+}
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Decode the data of an RPC packet
+pub fn parse_command(data: &[u8]) -> Result<Command<'_>, ImprovError> {
+    let [command, len, rest @ ..] = data else {
+        return Err(ImprovError::InvalidRpc);
+    };
+    let args = rest.get(..*len as usize).ok_or(ImprovError::InvalidRpc)?;
+    match command {
+        0x01 => {
+            let (ssid, rest) = read_str(args)?;
+            let (password, _) = read_str(rest)?;
+            Ok(Command::WifiSettings { ssid, password })
+        }
+        0x02 => Ok(Command::GetCurrentState),
+        0x03 => Ok(Command::GetDeviceInfo),
+        0x04 => Ok(Command::GetWifiNetworks),
+        _ => Err(ImprovError::UnknownRpc),
+    }
+}
+
+fn read_str(data: &[u8]) -> Result<(&str, &[u8]), ImprovError> {
+    let (len, rest) = data.split_first().ok_or(ImprovError::InvalidRpc)?;
+    if rest.len() < *len as usize {
+        return Err(ImprovError::InvalidRpc);
+    }
+    let (s, rest) = rest.split_at(*len as usize);
+    let s = core::str::from_utf8(s).map_err(|_| ImprovError::InvalidRpc)?;
+    Ok((s, rest))
+}
+
+/// Encode a packet, followed by a newline so it doesn't mess up the terminal
+pub fn encode(packet_type: PacketType, data: &[u8]) -> heapless::Vec<u8, { MAX_PACKET_LEN + 1 }> {
+    let mut packet = heapless::Vec::new();
+    packet.extend_from_slice(HEADER).ok();
+    packet.push(VERSION).ok();
+    packet.push(packet_type as u8).ok();
+    let len = data.len().min(u8::MAX as usize);
+    packet.push(len as u8).ok();
+    packet.extend_from_slice(&data[..len]).ok();
+    packet.push(checksum(&packet)).ok();
+    packet.push(b'\n').ok();
+    packet
+}
+
+/// Data of an RPC result packet, the strings are truncated if they don't fit
+pub fn rpc_result(command: u8, strings: &[&str]) -> heapless::Vec<u8, { u8::MAX as usize }> {
+    let mut data = heapless::Vec::new();
+    data.push(command).ok();
+    data.push(0).ok();
+    for s in strings {
+        let len = s.len().min(data.capacity() - data.len() - 1);
+        data.push(len as u8).ok();
+        data.extend_from_slice(&s.as_bytes()[..len]).ok();
+    }
+    data[1] = (data.len() - 2) as u8;
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Command, Feed, ImprovError, ImprovParser, PacketType, checksum, encode, parse_command,
+        rpc_result,
+    };
+
+    fn feed_all<'a>(parser: &'a mut ImprovParser, bytes: &[u8]) -> Feed<'a> {
+        let (last, rest) = bytes.split_last().unwrap();
+        for b in rest {
+            assert_eq!(parser.feed(*b), Feed::Pending);
+        }
+        parser.feed(*last)
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(&[]), 0);
+        assert_eq!(checksum(&[0x01, 0x02, 0xFF]), 0x02);
+        // "IMPROV" sums to 477
+        let packet = encode(PacketType::CurrentState, &[0x02]);
+        assert_eq!(
+            packet.as_slice(),
+            b"IMPROV\x01\x01\x01\x02\xe2\n".as_slice()
+        );
+    }
+
+    #[test]
+    fn test_parse_packet() {
+        let mut parser = ImprovParser::default();
+        let packet = encode(PacketType::Rpc, &[0x03, 0x00]);
+        let packet = &packet[..packet.len() - 1];
+        assert_eq!(
+            feed_all(&mut parser, packet),
+            Feed::Packet(PacketType::Rpc as u8, &[0x03, 0x00])
+        );
+        // The next packet starts fresh
+        assert_eq!(
+            feed_all(&mut parser, packet),
+            Feed::Packet(PacketType::Rpc as u8, &[0x03, 0x00])
+        );
+
+        let mut bad = heapless::Vec::<u8, 16>::from_slice(packet).unwrap();
+        *bad.last_mut().unwrap() ^= 1;
+        assert_eq!(feed_all(&mut parser, &bad), Feed::Invalid);
+        bad[6] = 2;
+        assert_eq!(feed_all(&mut parser, &bad), Feed::Invalid);
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let mut parser = ImprovParser::default();
+        assert_eq!(
+            parser.feed(b'x'),
+            Feed::Passthrough(heapless::Vec::from_slice(b"x").unwrap())
+        );
+        assert_eq!(parser.feed(b'I'), Feed::Pending);
+        assert_eq!(parser.feed(b'M'), Feed::Pending);
+        assert_eq!(
+            parser.feed(b'!'),
+            Feed::Passthrough(heapless::Vec::from_slice(b"IM!").unwrap())
+        );
+        // "I" may start a packet right after a false start
+        assert_eq!(parser.feed(b'I'), Feed::Pending);
+        assert_eq!(
+            parser.feed(b'I'),
+            Feed::Passthrough(heapless::Vec::from_slice(b"I").unwrap())
+        );
+        let packet = encode(PacketType::Rpc, &[0x02, 0x00]);
+        assert_eq!(
+            feed_all(&mut parser, &packet[1..packet.len() - 1]),
+            Feed::Packet(PacketType::Rpc as u8, &[0x02, 0x00])
+        );
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            parse_command(b"\x01\x0a\x04ssid\x04pass"),
+            Ok(Command::WifiSettings {
+                ssid: "ssid",
+                password: "pass"
+            })
+        );
+        assert_eq!(
+            parse_command(b"\x01\x06\x04ssid\x00"),
+            Ok(Command::WifiSettings {
+                ssid: "ssid",
+                password: ""
+            })
+        );
+        assert_eq!(parse_command(b"\x02\x00"), Ok(Command::GetCurrentState));
+        assert_eq!(parse_command(b"\x03\x00"), Ok(Command::GetDeviceInfo));
+        assert_eq!(parse_command(b"\x04\x00"), Ok(Command::GetWifiNetworks));
+        assert_eq!(parse_command(b"\x09\x00"), Err(ImprovError::UnknownRpc));
+        assert_eq!(parse_command(b"\x01"), Err(ImprovError::InvalidRpc));
+        assert_eq!(
+            parse_command(b"\x01\x0a\x04ssid\x05pass"),
+            Err(ImprovError::InvalidRpc)
+        );
+        assert_eq!(
+            parse_command(b"\x01\x06\x04ss\xffd\x00"),
+            Err(ImprovError::InvalidRpc)
+        );
+    }
+
+    #[test]
+    fn test_rpc_result() {
+        assert_eq!(rpc_result(0x01, &[]).as_slice(), b"\x01\x00");
+        assert_eq!(
+            rpc_result(0x03, &["esparrier", "0.7.0"]).as_slice(),
+            b"\x03\x10\x09esparrier\x050.7.0"
+        );
+    }
+}
@@ -1,5 +1,9 @@
 use embassy_time::{Duration, with_timeout};
-use embedded_graphics::image::ImageDrawable;
+use embedded_graphics::{
+    image::ImageDrawable,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+};
 use tinygif::Gif;
 
 use crate::IndicatorStatus;
@@ -25,6 +29,89 @@ const CONNECTING: &[u8] = include_bytes!("assets/connecting.gif");
 const INACTIVE: &[u8] = include_bytes!("assets/inactive.gif");
 const ACTIVE: &[u8] = include_bytes!("assets/active.gif");
 
+const PROGRESS_BAR_HEIGHT: u32 = 8;
+const PREVIEW_DURATION: Duration = Duration::from_secs(2);
+// Characters of the clipboard preview, 6 lines of 21 characters fit the 128x128 screen
+const PREVIEW_LINE_LENGTH: usize = 21;
+const PREVIEW_SIZE: usize = PREVIEW_LINE_LENGTH * 6;
+
+/// Clear the screen and show the title and the ASCII text wrapped into lines.
+fn draw_text<D>(display: &mut D, title: &str, text: &str)
+where
+    D: DrawTarget<Color = ColorFormat>,
+    D::Error: core::fmt::Debug,
+{
+    use embedded_graphics::{
+        mono_font::{MonoTextStyle, ascii::FONT_6X10},
+        text::{Baseline, Text},
+    };
+
+    display.clear(ColorFormat::BLACK).unwrap();
+    let title_style = MonoTextStyle::new(&FONT_6X10, ColorFormat::YELLOW);
+    let text_style = MonoTextStyle::new(&FONT_6X10, ColorFormat::WHITE);
+    Text::with_baseline(title, Point::new(2, 2), title_style, Baseline::Top)
+        .draw(display)
+        .unwrap();
+    for (i, line) in text.as_bytes().chunks(PREVIEW_LINE_LENGTH).enumerate() {
+        let line = core::str::from_utf8(line).unwrap_or_default();
+        Text::with_baseline(
+            line,
+            Point::new(2, 18 + i as i32 * 12),
+            text_style,
+            Baseline::Top,
+        )
+        .draw(display)
+        .unwrap();
+    }
+}
+
+/// Show the slot number and the beginning of the clipboard in the slot.
+async fn draw_clipboard_preview<D>(display: &mut D, slot: usize)
+where
+    D: DrawTarget<Color = ColorFormat>,
+    D::Error: core::fmt::Debug,
+{
+    use core::fmt::Write;
+
+    #[cfg(feature = "clipboard")]
+    let (count, preview) = (
+        crate::clipboard::get_clipboard_count().await,
+        crate::clipboard::get_clipboard_preview::<PREVIEW_SIZE>(slot)
+            .await
+            .unwrap_or_default(),
+    );
+    #[cfg(not(feature = "clipboard"))]
+    let (count, preview) = (0, heapless::String::<PREVIEW_SIZE>::new());
+
+    let mut title = heapless::String::<24>::new();
+    write!(title, "Slot {}/{}", slot + 1, count).ok();
+    // The preview only has printable ASCII characters
+    draw_text(display, &title, &preview);
+}
+
+/// Show how to reach the provisioning portal.
+fn draw_provisioning<D>(display: &mut D)
+where
+    D: DrawTarget<Color = ColorFormat>,
+    D::Error: core::fmt::Debug,
+{
+    use core::fmt::Write;
+
+    #[cfg(feature = "wifi")]
+    let ssid = crate::provisioning::get_ap_ssid().unwrap_or_default();
+    #[cfg(not(feature = "wifi"))]
+    let ssid = "";
+    // Each item starts on a new line
+    let mut text = heapless::String::<PREVIEW_SIZE>::new();
+    write!(
+        text,
+        "{:<21}{:<21}{:<21}",
+        "Join WiFi:", ssid, "Open 192.168.4.1"
+    )
+    .ok();
+    draw_text(display, "Setup", &text);
+}
+
 pub async fn start_indicator(config: IndicatorConfig, receiver: IndicatorReceiver) {
     let mut display = init_display(config);
 
@@ -33,14 +120,61 @@ pub async fn start_indicator(config: IndicatorConfig, receiver: IndicatorReceive
     let active_gif: Gif<'_, ColorFormat> = Gif::from_slice(ACTIVE).unwrap();
 
     let mut status = IndicatorStatus::WifiConnecting;
+    // Status to go back to after the clipboard preview
+    let mut last_status = status;
 
     loop {
+        if !matches!(status, IndicatorStatus::ClipboardSlot(_)) {
+            last_status = status;
+        }
         let gif = match status {
             IndicatorStatus::WifiConnecting => &connecting_gif,
             IndicatorStatus::WifiConnected(_) => &connecting_gif,
             IndicatorStatus::ServerConnecting => &connecting_gif,
             IndicatorStatus::ServerConnected => &inactive_gif,
             IndicatorStatus::Active => &active_gif,
+            IndicatorStatus::Typing(progress) => {
+                // Progress bar at the bottom, over whatever is on the screen
+                let size = display.bounding_box().size;
+                let bar = Rectangle::new(
+                    Point::new(0, size.height as i32 - PROGRESS_BAR_HEIGHT as i32),
+                    Size::new(size.width, PROGRESS_BAR_HEIGHT),
+                );
+                bar.into_styled(PrimitiveStyle::with_fill(ColorFormat::BLACK))
+                    .draw(&mut display)
+                    .unwrap();
+                Rectangle::new(
+                    bar.top_left,
+                    Size::new(size.width * progress as u32 / 100, PROGRESS_BAR_HEIGHT),
+                )
+                .into_styled(PrimitiveStyle::with_fill(ColorFormat::GREEN))
+                .draw(&mut display)
+                .unwrap();
+                status = receiver.receive().await;
+                continue;
+            }
+            IndicatorStatus::ClipboardSlot(slot) => {
+                draw_clipboard_preview(&mut display, slot as usize).await;
+                status = with_timeout(PREVIEW_DURATION, receiver.receive())
+                    .await
+                    .unwrap_or(last_status);
+                continue;
+            }
+            IndicatorStatus::FactoryReset => {
+                display.clear(ColorFormat::RED).unwrap();
+                status = receiver.receive().await;
+                continue;
+            }
+            IndicatorStatus::Provisioning => {
+                draw_provisioning(&mut display);
+                status = receiver.receive().await;
+                continue;
+            }
+            IndicatorStatus::Identify => {
+                display.clear(ColorFormat::CYAN).unwrap();
+                status = receiver.receive().await;
+                continue;
+            }
         };
         if status == IndicatorStatus::Active {
             // Don't waste time on animation, just show the first frame and wait for the next status forever
@@ -31,6 +31,22 @@ fn get_led_config(status: IndicatorStatus) -> LedConfig {
             on_duration: Duration::from_millis(1000),
             off_duration: Duration::from_millis(0),
         },
+        IndicatorStatus::Typing(_) | IndicatorStatus::ClipboardSlot(_) => LedConfig {
+            on_duration: Duration::from_millis(50),
+            off_duration: Duration::from_millis(50),
+        },
+        IndicatorStatus::FactoryReset => LedConfig {
+            on_duration: Duration::from_millis(20),
+            off_duration: Duration::from_millis(20),
+        },
+        IndicatorStatus::Provisioning => LedConfig {
+            on_duration: Duration::from_millis(100),
+            off_duration: Duration::from_millis(900),
+        },
+        IndicatorStatus::Identify => LedConfig {
+            on_duration: Duration::from_millis(250),
+            off_duration: Duration::from_millis(250),
+        },
     }
 }
 
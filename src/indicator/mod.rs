@@ -2,7 +2,7 @@ use embassy_net::Ipv4Cidr;
 use embassy_sync::once_lock::OnceLock;
 use log::info;
 
-use crate::running_state::get_running_state_mut;
+use crate::{reset_report::record_indicator_status, running_state::get_running_state_mut};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IndicatorStatus {
@@ -11,6 +11,34 @@ pub enum IndicatorStatus {
     ServerConnecting,
     ServerConnected,
     Active,
+    // Typing the clipboard, progress in percent
+    Typing(u8),
+    // A clipboard slot is selected, only shown by the graphical indicator
+    ClipboardSlot(u8),
+    // Erasing the config, the board reboots afterwards
+    FactoryReset,
+    // Waiting for the config on the provisioning access point
+    Provisioning,
+    // Requested by an Improv client to find the board, restored after a while
+    Identify,
+}
+
+impl IndicatorStatus {
+    /// Number of the status without its data, kept across resets, 0 means none
+    pub fn code(&self) -> u8 {
+        match self {
+            IndicatorStatus::WifiConnecting => 1,
+            IndicatorStatus::WifiConnected(_) => 2,
+            IndicatorStatus::ServerConnecting => 3,
+            IndicatorStatus::ServerConnected => 4,
+            IndicatorStatus::Active => 5,
+            IndicatorStatus::Typing(_) => 6,
+            IndicatorStatus::ClipboardSlot(_) => 7,
+            IndicatorStatus::FactoryReset => 8,
+            IndicatorStatus::Provisioning => 9,
+            IndicatorStatus::Identify => 10,
+        }
+    }
 }
 
 type IndicatorSender = embassy_sync::channel::Sender<
@@ -106,6 +134,37 @@ pub async fn set_indicator_status(status: IndicatorStatus) {
             guard.server_connected = true;
             guard.active = true;
         }
+        IndicatorStatus::Typing(progress) => {
+            get_running_state_mut().await.typing_progress = Some(progress);
+        }
+        IndicatorStatus::ClipboardSlot(_) => {
+            if !cfg!(feature = "graphics") {
+                // Other indicators can't show it, keep the current status
+                return;
+            }
+        }
+        IndicatorStatus::FactoryReset
+        | IndicatorStatus::Provisioning
+        | IndicatorStatus::Identify => {}
     }
+    record_indicator_status(&status);
     INDICATOR_SENDER.get().await.try_send(status).ok();
 }
+
+/// Show the connection status again after a temporary status like `Typing`.
+pub async fn restore_indicator_status() {
+    let status = {
+        let mut guard = get_running_state_mut().await;
+        guard.typing_progress = None;
+        if guard.active {
+            IndicatorStatus::Active
+        } else if guard.server_connected {
+            IndicatorStatus::ServerConnected
+        } else if let Some(ip_address) = guard.ip_address {
+            IndicatorStatus::WifiConnected(ip_address)
+        } else {
+            IndicatorStatus::WifiConnecting
+        }
+    };
+    set_indicator_status(status).await;
+}
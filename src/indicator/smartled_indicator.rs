@@ -42,6 +42,18 @@ const GREEN: Hsv = Hsv {
     val: 255,
 };
 
+const CYAN: Hsv = Hsv {
+    hue: 180,
+    sat: 255,
+    val: 255,
+};
+
+const WHITE: Hsv = Hsv {
+    hue: 0,
+    sat: 0,
+    val: 255,
+};
+
 async fn wait_for_duration(
     duration: Duration,
     receiver: IndicatorReceiver,
@@ -172,6 +184,18 @@ pub async fn start_indicator(config: IndicatorConfig, receiver: IndicatorReceive
                 )
                 .await;
             }
+            IndicatorStatus::Typing(_) | IndicatorStatus::ClipboardSlot(_) => {
+                status = fade_in_out(&mut led, CYAN, receiver, 0, config.max_brightness, 4).await;
+            }
+            IndicatorStatus::FactoryReset => {
+                status = fade_in_out(&mut led, WHITE, receiver, 0, config.max_brightness, 2).await;
+            }
+            IndicatorStatus::Provisioning => {
+                status = fade_in_out(&mut led, WHITE, receiver, 0, config.max_brightness, 20).await;
+            }
+            IndicatorStatus::Identify => {
+                status = fade_in_out(&mut led, YELLOW, receiver, 0, config.max_brightness, 2).await;
+            }
         }
     }
 }
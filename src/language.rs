@@ -0,0 +1,111 @@
+//! Following the keyboard language of the server.
+//!
+//! The layout the host uses can't be read, so the first language the server announces after boot
+//! is taken as the one the host already uses, and a switch chord is only pressed when the server
+//! language changes after that. The applied language is kept across reconnects.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+// Index of the applied `language_sync` entry
+static APPLIED_LANGUAGE: AtomicU8 = AtomicU8::new(NO_LANGUAGE);
+// No language was announced since boot
+const NO_LANGUAGE: u8 = 0xFF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LanguageChange {
+    // The language is not configured or already applied
+    None,
+    // The first configured language since boot, the host is assumed to use it already
+    Initial(usize),
+    // The server switched to another configured language
+    Switch(usize),
+}
+
+/// Find the configured language that is active in the `languages` announced by the server.
+///
+/// `configured` are the languages of the `language_sync` entries, and `applied` is the index of
+/// the entry applied last.
+pub fn language_change<'a>(
+    configured: impl IntoIterator<Item = &'a str>,
+    languages: &str,
+    applied: Option<usize>,
+) -> LanguageChange {
+    // The active language comes first
+    let found = configured.into_iter().position(|language| {
+        languages
+            .get(..language.len())
+            .is_some_and(|l| l.eq_ignore_ascii_case(language))
+    });
+    match (found, applied) {
+        (None, _) => LanguageChange::None,
+        (Some(index), Some(applied)) if index == applied => LanguageChange::None,
+        (Some(index), None) => LanguageChange::Initial(index),
+        (Some(index), Some(_)) => LanguageChange::Switch(index),
+    }
+}
+
+/// Like `language_change`, and remember the entry as applied.
+pub fn sync_language<'a>(
+    configured: impl IntoIterator<Item = &'a str>,
+    languages: &str,
+) -> LanguageChange {
+    let applied = match APPLIED_LANGUAGE.load(Ordering::Relaxed) {
+        NO_LANGUAGE => None,
+        index => Some(index as usize),
+    };
+    let change = language_change(configured, languages, applied);
+    if let LanguageChange::Initial(index) | LanguageChange::Switch(index) = change {
+        APPLIED_LANGUAGE.store(index as u8, Ordering::Relaxed);
+    }
+    change
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LanguageChange, language_change};
+
+    const CONFIGURED: [&str; 2] = ["en", "de"];
+
+    #[test]
+    fn test_active_language() {
+        // The active language is the first one announced
+        assert_eq!(
+            language_change(CONFIGURED, "deen", None),
+            LanguageChange::Initial(1)
+        );
+        assert_eq!(
+            language_change(CONFIGURED, "EN", Some(1)),
+            LanguageChange::Switch(0)
+        );
+        // Only the active language counts
+        assert_eq!(
+            language_change(CONFIGURED, "frde", Some(0)),
+            LanguageChange::None
+        );
+    }
+
+    #[test]
+    fn test_repeated_language() {
+        assert_eq!(
+            language_change(CONFIGURED, "en", Some(0)),
+            LanguageChange::None
+        );
+        assert_eq!(
+            language_change(CONFIGURED, "de", Some(1)),
+            LanguageChange::None
+        );
+    }
+
+    #[test]
+    fn test_unknown_language() {
+        assert_eq!(
+            language_change(CONFIGURED, "fr", None),
+            LanguageChange::None
+        );
+        assert_eq!(
+            language_change(CONFIGURED, "", Some(1)),
+            LanguageChange::None
+        );
+        assert_eq!(language_change([], "en", None), LanguageChange::None);
+    }
+}
@@ -1,31 +1,71 @@
 #![no_std]
 
+extern crate alloc;
+
 mod barrier_client;
 #[cfg(feature = "clipboard")]
+mod button;
+#[cfg(feature = "clipboard")]
 mod clipboard;
 mod config;
 mod control;
 #[cfg(feature = "smartled")]
 mod esp_hal_smartled;
+mod gesture;
+mod health;
 mod hid_report_writer;
+mod hotkey;
+#[cfg(feature = "wifi")]
+mod improv;
 mod indicator;
+mod language;
+mod logger;
 #[cfg(feature = "ota")]
 mod ota;
+#[cfg(feature = "wifi")]
+mod provisioning;
+mod reconnect;
+mod reset_report;
 mod running_state;
+mod screen_mapping;
+mod serial;
+mod shell;
 mod synergy_hid;
+mod typing;
 mod usb_actuator;
 
 pub mod constants;
 pub use barrier_client::*;
 #[cfg(feature = "clipboard")]
-pub use clipboard::{button_task, set_clipboard};
+pub use button::button_task;
+#[cfg(feature = "clipboard")]
+pub use clipboard::{
+    cancel_paste, get_typing_layout, is_typing, paste_task, request_paste, set_clipboard,
+    set_typing_layout,
+};
 pub use config::{AppConfig, ConfigStore};
+pub use gesture::{ButtonAction, ButtonConfig, Gesture, GestureRecognizer};
+pub use health::{HealthTask, check_health, check_in, check_out};
 pub use hid_report_writer::{HidReport, send_hid_report, start_hid_task};
+pub use hotkey::HotkeyTracker;
 pub use indicator::*;
+pub use logger::{SyslogConfig, init_logger, syslog_task};
 #[cfg(feature = "ota")]
 pub use ota::OTA_IN_PROGRESS;
-pub use running_state::{RunningState, get_running_state};
-pub use synergy_hid::{ReportType, SynergyHid};
+#[cfg(feature = "wifi")]
+pub use provisioning::{
+    WifiSettings, answer_wifi_settings, is_provisioning_required, request_provisioning,
+    start_provisioning, wait_wifi_settings,
+};
+pub use reconnect::{Reconnect, ReconnectDecision, ReconnectPolicy};
+pub use reset_report::{ResetCause, ResetReport, get_reset_report, init_reset_report};
+pub use running_state::{
+    ConnectionStats, RunningState, get_running_state, get_running_state_mut, get_screen_size,
+    set_screen_size,
+};
+pub use screen_mapping::ScreenMapping;
+pub use synergy_hid::{ReportType, SynergyHid, TypingLayout};
+pub use typing::TypingConfig;
 pub use usb_actuator::UsbActuator;
 
 #[macro_export]
@@ -0,0 +1,238 @@
+//! Logger printing to the UART like `esp_println` does, and keeping the latest lines in a RAM ring
+//! buffer so they can be read over the control interface.
+//!
+//! If a syslog server is configured, the lines are also queued for `syslog_task`, the queue is
+//! bounded and full queues drop lines, so logging never waits for the network.
+//!
+//! The level filter comes from the `ESP_LOG` environment variable at build time, e.g.
+//! `info,esp_wifi=error`, the default level can be changed at runtime.
+
+mod ring;
+mod syslog;
+
+use core::{
+    cell::RefCell,
+    fmt::Write as _,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use critical_section::Mutex;
+use embassy_net::{Stack, udp::PacketMetadata, udp::UdpSocket};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::Instant;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::{AppConfig, mk_static};
+
+use ring::LogRing;
+pub use syslog::SyslogConfig;
+
+pub const LOG_BUFFER_SIZE: usize = 8192;
+// Longer lines are cut in the ring buffer, the UART gets them in full
+const MAX_LINE_LENGTH: usize = 256;
+const MAX_FILTERS: usize = 8;
+// Lines waiting to be sent to the syslog server
+const SYSLOG_QUEUE_SIZE: usize = 16;
+
+static LOG_BUFFER: Mutex<RefCell<LogRing<LOG_BUFFER_SIZE>>> =
+    Mutex::new(RefCell::new(LogRing::new()));
+static LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Info as usize);
+// Highest level of the targets in `ESP_LOG`
+static MAX_FILTER_LEVEL: AtomicUsize = AtomicUsize::new(0);
+// Minimum level sent to the syslog server, `Off` until `syslog_task` runs
+static SYSLOG_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Off as usize);
+static SYSLOG_QUEUE: Channel<CriticalSectionRawMutex, SyslogLine, SYSLOG_QUEUE_SIZE> =
+    Channel::new();
+// Lines dropped because the queue was full
+static SYSLOG_DROPPED: AtomicU32 = AtomicU32::new(0);
+
+struct SyslogLine {
+    level: Level,
+    uptime_ms: u64,
+    text: heapless::String<MAX_LINE_LENGTH>,
+}
+
+struct Logger {
+    // Levels of the targets starting with the prefix
+    filters: heapless::Vec<(&'static str, LevelFilter), MAX_FILTERS>,
+}
+
+impl Logger {
+    fn max_level(&self, target: &str) -> LevelFilter {
+        self.filters
+            .iter()
+            .find(|(prefix, _)| target.starts_with(prefix))
+            .map(|(_, level)| *level)
+            .unwrap_or_else(get_log_level)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.max_level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        esp_println::println!("{} - {}", record.level(), record.args());
+
+        let now = Instant::now().as_millis();
+        let mut line = heapless::String::<MAX_LINE_LENGTH>::new();
+        write!(
+            line,
+            "{}.{:03} {:<5} {}",
+            now / 1000,
+            now % 1000,
+            record.level(),
+            record.args()
+        )
+        .ok();
+        critical_section::with(|cs| {
+            let mut buffer = LOG_BUFFER.borrow_ref_mut(cs);
+            buffer.write(line.as_bytes());
+            buffer.write(b"\n");
+        });
+
+        if record.level() as usize <= SYSLOG_LEVEL.load(Ordering::Relaxed) {
+            let mut text = heapless::String::new();
+            write!(text, "{}", record.args()).ok();
+            let line = SyslogLine {
+                level: record.level(),
+                uptime_ms: now,
+                text,
+            };
+            if SYSLOG_QUEUE.try_send(line).is_err() {
+                SYSLOG_DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Parse the `ESP_LOG` filter, the default level and the levels of the targets
+fn parse_filters(
+    filter: &'static str,
+) -> (
+    LevelFilter,
+    heapless::Vec<(&'static str, LevelFilter), MAX_FILTERS>,
+) {
+    let mut level = LevelFilter::Info;
+    let mut filters = heapless::Vec::new();
+    for item in filter.split(',').map(str::trim) {
+        match item.split_once('=') {
+            Some((target, value)) => {
+                if let Ok(value) = value.trim().parse() {
+                    filters.push((target.trim(), value)).ok();
+                }
+            }
+            None => {
+                if let Ok(value) = item.parse() {
+                    level = value;
+                }
+            }
+        }
+    }
+    (level, filters)
+}
+
+/// Install the logger, replaces `esp_println::logger::init_logger_from_env`.
+pub fn init_logger() {
+    let (level, filters) = parse_filters(option_env!("ESP_LOG").unwrap_or("info"));
+    let max_filter_level = filters.iter().map(|(_, level)| *level as usize).max();
+    MAX_FILTER_LEVEL.store(max_filter_level.unwrap_or_default(), Ordering::Relaxed);
+    let logger = mk_static!(Logger, Logger { filters });
+    if log::set_logger(logger).is_ok() {
+        set_log_level(level);
+    }
+}
+
+pub fn get_log_level() -> LevelFilter {
+    LevelFilter::iter()
+        .nth(LEVEL.load(Ordering::Relaxed))
+        .unwrap_or(LevelFilter::Info)
+}
+
+/// Change the default level, the targets with their own level in `ESP_LOG` keep it.
+///
+/// The levels compiled out, e.g. debug and trace in release builds, can't be enabled, returns the
+/// level actually set.
+pub fn set_log_level(level: LevelFilter) -> LevelFilter {
+    let level = level.min(log::STATIC_MAX_LEVEL);
+    LEVEL.store(level as usize, Ordering::Relaxed);
+    // The targets with a higher level still need to pass the `log` macros
+    let max = MAX_FILTER_LEVEL.load(Ordering::Relaxed).max(level as usize);
+    log::set_max_level(LevelFilter::iter().nth(max).unwrap_or(level));
+    log::info!("Log level: {level}");
+    level
+}
+
+/// Copy the log from `offset` into `out`, returns the offset actually read from, the length and
+/// the offset of the end of the log.
+pub fn read_log(offset: u32, out: &mut [u8]) -> (u32, usize, u32) {
+    critical_section::with(|cs| {
+        let buffer = LOG_BUFFER.borrow_ref(cs);
+        let (offset, len) = buffer.read(offset, out);
+        (offset, len, buffer.end())
+    })
+}
+
+/// Send the queued log lines to the syslog server in the config, does nothing if there is none.
+#[embassy_executor::task]
+pub async fn syslog_task(stack: Stack<'static>) {
+    let config = AppConfig::get();
+    let Some(syslog) = &config.syslog else {
+        return;
+    };
+    let Some(endpoint) = config.get_syslog_endpoint() else {
+        log::warn!("Invalid syslog host '{}', syslog disabled", syslog.host);
+        return;
+    };
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 16];
+    let mut tx_buffer = [0; 2048];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    // Any local port
+    socket.bind(0).unwrap();
+    log::info!("Sending log to syslog server {endpoint}");
+    SYSLOG_LEVEL.store(syslog.level as usize, Ordering::Relaxed);
+
+    let mut message = heapless::String::new();
+    loop {
+        let line = SYSLOG_QUEUE.receive().await;
+        // Failing to send is not logged, it would only queue more lines that can't be sent
+        stack.wait_config_up().await;
+        let dropped = SYSLOG_DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            let mut text = heapless::String::<64>::new();
+            write!(text, "{dropped} log lines dropped").ok();
+            syslog::format_message(
+                &mut message,
+                syslog.facility,
+                Level::Warn,
+                &config.screen_name,
+                line.uptime_ms,
+                &text,
+            );
+            socket.send_to(message.as_bytes(), endpoint).await.ok();
+        }
+        syslog::format_message(
+            &mut message,
+            syslog.facility,
+            line.level,
+            &config.screen_name,
+            line.uptime_ms,
+            &line.text,
+        );
+        socket.send_to(message.as_bytes(), endpoint).await.ok();
+    }
+}
@@ -0,0 +1,125 @@
+//! Fixed-size byte ring keeping the latest log output.
+
+/// Readers address the bytes by the offset since boot, so they can page through the log while it
+/// is being written. The offset wraps after 4GB of logs, which is not a concern in practice.
+pub struct LogRing<const N: usize> {
+    buf: [u8; N],
+    // Total bytes written since boot
+    written: u32,
+}
+
+impl<const N: usize> LogRing<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            written: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        // Only the tail fits, the head still counts
+        let skip = data.len().saturating_sub(N);
+        self.written = self.written.wrapping_add(skip as u32);
+        let data = &data[skip..];
+        let pos = self.written as usize % N;
+        let first = (N - pos).min(data.len());
+        self.buf[pos..pos + first].copy_from_slice(&data[..first]);
+        self.buf[..data.len() - first].copy_from_slice(&data[first..]);
+        self.written = self.written.wrapping_add(data.len() as u32);
+    }
+
+    /// Offset of the oldest byte still in the buffer
+    pub fn start(&self) -> u32 {
+        self.written.saturating_sub(N as u32)
+    }
+
+    /// Offset of the next byte to be written
+    pub fn end(&self) -> u32 {
+        self.written
+    }
+
+    /// Copy the bytes from `offset` into `out`, returns the offset actually read from and the
+    /// length.
+    ///
+    /// If `offset` is already overwritten, reading starts at the first complete line.
+    pub fn read(&self, offset: u32, out: &mut [u8]) -> (u32, usize) {
+        let offset = if offset < self.start() {
+            self.first_line()
+        } else {
+            offset.min(self.written)
+        };
+        let len = ((self.written - offset) as usize).min(out.len());
+        for (i, b) in out[..len].iter_mut().enumerate() {
+            *b = self.buf[(offset as usize + i) % N];
+        }
+        (offset, len)
+    }
+
+    fn first_line(&self) -> u32 {
+        let start = self.start();
+        if start == 0 {
+            return 0;
+        }
+        // The oldest line is cut once the buffer wrapped around
+        (start..self.written)
+            .find(|offset| self.buf[*offset as usize % N] == b'\n')
+            .map(|offset| offset + 1)
+            .unwrap_or(start)
+    }
+}
+
+impl<const N: usize> Default for LogRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LogRing;
+
+    #[test]
+    fn test_read_write() {
+        let mut ring = LogRing::<16>::new();
+        let mut out = [0; 16];
+        assert_eq!(ring.read(0, &mut out), (0, 0));
+        ring.write(b"one\ntwo\n");
+        assert_eq!((ring.start(), ring.end()), (0, 8));
+        assert_eq!(ring.read(0, &mut out), (0, 8));
+        assert_eq!(&out[..8], b"one\ntwo\n");
+        // Paging
+        assert_eq!(ring.read(2, &mut out[..3]), (2, 3));
+        assert_eq!(&out[..3], b"e\nt");
+        // Nothing new
+        assert_eq!(ring.read(8, &mut out), (8, 0));
+        assert_eq!(ring.read(100, &mut out), (8, 0));
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        let mut ring = LogRing::<16>::new();
+        let mut out = [0; 16];
+        ring.write(b"one\ntwo\nthree\n");
+        ring.write(b"four\n");
+        assert_eq!((ring.start(), ring.end()), (3, 19));
+        // "one" is cut, start after it
+        assert_eq!(ring.read(0, &mut out), (4, 15));
+        assert_eq!(&out[..15], b"two\nthree\nfour\n");
+        assert_eq!(ring.read(8, &mut out), (8, 11));
+        assert_eq!(&out[..11], b"three\nfour\n");
+    }
+
+    #[test]
+    fn test_long_write() {
+        let mut ring = LogRing::<8>::new();
+        let mut out = [0; 8];
+        ring.write(b"0123456789abcdef");
+        assert_eq!((ring.start(), ring.end()), (8, 16));
+        // No complete line, read what is left
+        assert_eq!(ring.read(0, &mut out), (8, 8));
+        assert_eq!(&out, b"89abcdef");
+        ring.write(b"\n");
+        // The cut line is skipped
+        assert_eq!(ring.read(0, &mut out), (17, 0));
+    }
+}
@@ -0,0 +1,124 @@
+//! Syslog target, the log lines are sent to a syslog server as RFC 5424 messages over UDP.
+
+use core::fmt::Write as _;
+
+use log::{Level, LevelFilter};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_PORT: u16 = 514;
+// local0
+const DEFAULT_FACILITY: u8 = 16;
+const MAX_FACILITY: u8 = 23;
+const APP_NAME: &str = "esparrier";
+// A datagram this size is never fragmented
+pub const MAX_MESSAGE_SIZE: usize = 480;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SyslogConfig {
+    // IPv4 address of the syslog server, host names are not supported
+    pub host: heapless::String<16>,
+    #[serde(default = "get_default_port")]
+    pub port: u16,
+    // 0-23, e.g. 1 is user, 16-23 are local0-local7
+    #[serde(default = "get_default_facility")]
+    pub facility: u8,
+    // Messages below this level are not sent, they must pass the log level as well
+    #[serde(default = "get_default_level")]
+    pub level: LevelFilter,
+}
+
+fn get_default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+fn get_default_facility() -> u8 {
+    DEFAULT_FACILITY
+}
+
+fn get_default_level() -> LevelFilter {
+    LevelFilter::Info
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Format an RFC 5424 message.
+///
+/// There is no wall clock, so the timestamp is left out and the uptime goes into the `meta`
+/// structured data, in hundredths of a second as the RFC defines it. The message is cut if it
+/// doesn't fit.
+pub fn format_message(
+    out: &mut heapless::String<MAX_MESSAGE_SIZE>,
+    facility: u8,
+    level: Level,
+    hostname: &str,
+    uptime_ms: u64,
+    msg: &str,
+) {
+    out.clear();
+    let pri = facility.min(MAX_FACILITY) * 8 + severity(level);
+    write!(out, "<{pri}>1 - ").ok();
+    // The hostname is printable ASCII without spaces
+    let mut empty = true;
+    for c in hostname.chars() {
+        let c = if c.is_ascii_graphic() { c } else { '_' };
+        if out.push(c).is_err() {
+            break;
+        }
+        empty = false;
+    }
+    if empty {
+        out.push('-').ok();
+    }
+    write!(
+        out,
+        " {APP_NAME} - - [meta sysUpTime=\"{}\"] ",
+        uptime_ms / 10
+    )
+    .ok();
+    for c in msg.chars() {
+        if out.push(c).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::Level;
+
+    use super::format_message;
+
+    #[test]
+    fn test_format_message() {
+        let mut out = heapless::String::new();
+        format_message(&mut out, 16, Level::Warn, "MY SCREEN", 12345, "Hello");
+        assert_eq!(
+            out.as_str(),
+            "<132>1 - MY_SCREEN esparrier - - [meta sysUpTime=\"1234\"] Hello"
+        );
+        format_message(&mut out, 1, Level::Debug, "", 0, "");
+        assert_eq!(
+            out.as_str(),
+            "<15>1 - - esparrier - - [meta sysUpTime=\"0\"] "
+        );
+        // Out of range facilities are clamped
+        format_message(&mut out, 99, Level::Error, "kvm", 0, "x");
+        assert!(out.starts_with("<187>1 - kvm "));
+    }
+
+    #[test]
+    fn test_format_long_message() {
+        let mut out = heapless::String::new();
+        let msg = "x".repeat(1000);
+        format_message(&mut out, 16, Level::Info, "kvm", 0, &msg);
+        assert_eq!(out.len(), out.capacity());
+        assert!(out.ends_with("xxx"));
+    }
+}
@@ -0,0 +1,120 @@
+//! Minimal DHCP server handing out addresses of the provisioning network.
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTIONS_OFFSET: usize = 240;
+const MAX_LEASES: usize = 8;
+const LEASE_TIME: u32 = 3600;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_END: u8 = 255;
+
+/// Leases by client hardware address, the server is `x.x.x.1`, clients get `x.x.x.2` and above
+pub struct DhcpServer {
+    addr: [u8; 4],
+    leases: heapless::Vec<[u8; 6], MAX_LEASES>,
+}
+
+impl DhcpServer {
+    pub fn new(addr: [u8; 4]) -> Self {
+        Self {
+            addr,
+            leases: heapless::Vec::new(),
+        }
+    }
+
+    fn lease(&mut self, mac: [u8; 6]) -> [u8; 4] {
+        let index = match self.leases.iter().position(|m| *m == mac) {
+            Some(index) => index,
+            None => {
+                if self.leases.is_full() {
+                    // Reuse the oldest lease
+                    self.leases.remove(0);
+                }
+                self.leases.push(mac).ok();
+                self.leases.len() - 1
+            }
+        };
+        let mut ip = self.addr;
+        ip[3] = 2 + index as u8;
+        ip
+    }
+
+    /// Write the reply of the request into `out`, returns the reply length, `None` if the request
+    /// needs no reply.
+    pub fn reply(&mut self, request: &[u8], out: &mut [u8]) -> Option<usize> {
+        // BOOTREQUEST with Ethernet addresses
+        if request.len() < OPTIONS_OFFSET
+            || request[0] != 1
+            || request[1] != 1
+            || request[2] != 6
+            || request[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let mut message_type = None;
+        let mut pos = OPTIONS_OFFSET;
+        while pos < request.len() {
+            let code = request[pos];
+            match code {
+                0 => pos += 1,
+                OPT_END => break,
+                _ => {
+                    let len = *request.get(pos + 1)? as usize;
+                    if code == OPT_MESSAGE_TYPE && len == 1 {
+                        message_type = Some(*request.get(pos + 2)?);
+                    }
+                    pos += 2 + len;
+                }
+            }
+        }
+        let reply_type = match message_type? {
+            DISCOVER => OFFER,
+            REQUEST => ACK,
+            _ => return None,
+        };
+
+        let mac: [u8; 6] = request[28..34].try_into().ok()?;
+        let ip = self.lease(mac);
+        let options: [(u8, &[u8]); 6] = [
+            (OPT_MESSAGE_TYPE, &[reply_type]),
+            (OPT_SERVER_ID, &self.addr),
+            (OPT_LEASE_TIME, &LEASE_TIME.to_be_bytes()),
+            (OPT_SUBNET_MASK, &[255, 255, 255, 0]),
+            (OPT_ROUTER, &self.addr),
+            (OPT_DNS, &self.addr),
+        ];
+        let len = OPTIONS_OFFSET + options.iter().map(|(_, v)| 2 + v.len()).sum::<usize>() + 1;
+        if out.len() < len {
+            return None;
+        }
+        out[..len].fill(0);
+        // BOOTREPLY, keep the hardware type, transaction id, flags and the client address
+        out[0] = 2;
+        out[1..4].copy_from_slice(&request[1..4]);
+        out[4..8].copy_from_slice(&request[4..8]);
+        out[10..12].copy_from_slice(&request[10..12]);
+        out[16..20].copy_from_slice(&ip);
+        out[20..24].copy_from_slice(&self.addr);
+        out[28..44].copy_from_slice(&request[28..44]);
+        out[236..240].copy_from_slice(&MAGIC_COOKIE);
+        let mut pos = OPTIONS_OFFSET;
+        for (code, value) in options {
+            out[pos] = code;
+            out[pos + 1] = value.len() as u8;
+            out[pos + 2..pos + 2 + value.len()].copy_from_slice(value);
+            pos += 2 + value.len();
+        }
+        out[pos] = OPT_END;
+        Some(len)
+    }
+}
@@ -0,0 +1,61 @@
+//! Catch-all DNS responder, every A query is answered with the portal address so the OS shows the
+//! captive portal.
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+/// Write the reply of the query into `out`, returns the reply length, `None` if it is not a query.
+pub fn reply(query: &[u8], addr: [u8; 4], out: &mut [u8]) -> Option<usize> {
+    if query.len() < HEADER_LEN || query[2] & 0x80 != 0 {
+        return None;
+    }
+    // Only the 1st question is answered
+    let qdcount = u16::from_be_bytes([query[4], query[5]]);
+    if qdcount == 0 {
+        return None;
+    }
+    let mut pos = HEADER_LEN;
+    loop {
+        let len = *query.get(pos)? as usize;
+        if len == 0 {
+            pos += 1;
+            break;
+        }
+        // Compressed names are not expected in questions
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        pos += 1 + len;
+    }
+    let question = query.get(HEADER_LEN..pos + 4)?;
+    let qtype = u16::from_be_bytes([question[question.len() - 4], question[question.len() - 3]]);
+    let qclass = u16::from_be_bytes([question[question.len() - 2], question[question.len() - 1]]);
+    let answer = qtype == TYPE_A && qclass == CLASS_IN;
+
+    let len = HEADER_LEN + question.len() + if answer { 16 } else { 0 };
+    if out.len() < len {
+        return None;
+    }
+    out[0..2].copy_from_slice(&query[0..2]);
+    // Response, authoritative, keep the recursion desired bit
+    out[2] = 0x84 | (query[2] & 0x01);
+    // Recursion available, no error
+    out[3] = 0x80;
+    out[4..6].copy_from_slice(&1u16.to_be_bytes());
+    out[6..8].copy_from_slice(&(answer as u16).to_be_bytes());
+    out[8..12].fill(0);
+    out[HEADER_LEN..HEADER_LEN + question.len()].copy_from_slice(question);
+    if answer {
+        let a = &mut out[HEADER_LEN + question.len()..len];
+        // Pointer to the name in the question
+        a[0..2].copy_from_slice(&(0xC000 | HEADER_LEN as u16).to_be_bytes());
+        a[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        a[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        // TTL
+        a[6..10].copy_from_slice(&60u32.to_be_bytes());
+        a[10..12].copy_from_slice(&4u16.to_be_bytes());
+        a[12..16].copy_from_slice(&addr);
+    }
+    Some(len)
+}
@@ -0,0 +1,225 @@
+//! Minimal HTTP/1.1 handling of the provisioning portal, one request per connection.
+
+use core::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub method: Method,
+    // Path without the query string
+    pub path: &'a str,
+    pub content_length: usize,
+    // Length of the request line and headers, the body starts here
+    pub header_len: usize,
+}
+
+impl Request<'_> {
+    /// Total length of the request including the body
+    pub fn len(&self) -> usize {
+        self.header_len + self.content_length
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HttpError {
+    BadRequest,
+    TooLarge,
+}
+
+impl HttpError {
+    pub fn status(&self) -> u16 {
+        match self {
+            HttpError::BadRequest => 400,
+            HttpError::TooLarge => 413,
+        }
+    }
+}
+
+/// Parse the request line and headers, returns `Ok(None)` if the headers are not complete yet.
+///
+/// Requests with a body larger than `max_body` are rejected.
+pub fn parse_request(buf: &[u8], max_body: usize) -> Result<Option<Request<'_>>, HttpError> {
+    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+    let head = core::str::from_utf8(&buf[..end]).map_err(|_| HttpError::BadRequest)?;
+    let mut lines = head.split("\r\n");
+    let mut parts = lines.next().unwrap_or_default().split(' ');
+    let method = match parts.next() {
+        Some("GET") => Method::Get,
+        Some("POST") => Method::Post,
+        Some(m) if !m.is_empty() => Method::Other,
+        _ => return Err(HttpError::BadRequest),
+    };
+    let target = parts.next().ok_or(HttpError::BadRequest)?;
+    if !target.starts_with('/') || !parts.next().is_some_and(|v| v.starts_with("HTTP/1.")) {
+        return Err(HttpError::BadRequest);
+    }
+    let path = target.split(['?', '#']).next().unwrap_or(target);
+
+    let mut content_length = 0;
+    for line in lines {
+        let (name, value) = line.split_once(':').ok_or(HttpError::BadRequest)?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse().map_err(|_| HttpError::BadRequest)?;
+        }
+    }
+    if content_length > max_body {
+        return Err(HttpError::TooLarge);
+    }
+    Ok(Some(Request {
+        method,
+        path,
+        content_length,
+        header_len: end + 4,
+    }))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Route {
+    // The config form
+    Index,
+    // Current config as JSON, the password is not included
+    GetConfig,
+    // New config as JSON, committed and the board reboots if it is valid
+    PostConfig,
+    // Unknown path, redirected to the form so the OS shows the captive portal
+    Redirect,
+    MethodNotAllowed,
+}
+
+pub fn route(request: &Request) -> Route {
+    match (request.method, request.path) {
+        (Method::Get, "/" | "/index.html") => Route::Index,
+        (Method::Get, "/api/config") => Route::GetConfig,
+        (Method::Post, "/api/config") => Route::PostConfig,
+        (_, "/" | "/index.html" | "/api/config") => Route::MethodNotAllowed,
+        (Method::Get, _) => Route::Redirect,
+        _ => Route::MethodNotAllowed,
+    }
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        302 => "Found",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Response status line and headers, the body is written separately
+pub fn response_head<const N: usize>(
+    status: u16,
+    content_type: &str,
+    content_length: usize,
+    location: Option<&str>,
+) -> heapless::String<N> {
+    let mut head = heapless::String::new();
+    write!(head, "HTTP/1.1 {status} {}\r\n", status_text(status)).ok();
+    if let Some(location) = location {
+        write!(head, "Location: {location}\r\n").ok();
+    }
+    write!(
+        head,
+        "Content-Type: {content_type}\r\nContent-Length: {content_length}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n"
+    )
+    .ok();
+    head
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HttpError, Method, Route, parse_request, response_head, route};
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            parse_request(b"GET / HTTP/1.1\r\nHost: x\r\n", 16),
+            Ok(None)
+        );
+
+        let req = parse_request(b"GET /index.html?a=1 HTTP/1.1\r\nHost: x\r\n\r\n", 16)
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.method, Method::Get);
+        assert_eq!(req.path, "/index.html");
+        assert_eq!(req.content_length, 0);
+        assert_eq!(req.header_len, 41);
+
+        let buf = b"POST /api/config HTTP/1.1\r\ncontent-length: 7\r\n\r\n{\"a\":1}";
+        let req = parse_request(buf, 16).unwrap().unwrap();
+        assert_eq!(req.method, Method::Post);
+        assert_eq!(req.content_length, 7);
+        assert_eq!(req.len(), buf.len());
+        assert_eq!(&buf[req.header_len..req.len()], b"{\"a\":1}");
+    }
+
+    #[test]
+    fn test_parse_bad_request() {
+        assert_eq!(
+            parse_request(
+                b"POST /api/config HTTP/1.1\r\nContent-Length: 17\r\n\r\n",
+                16
+            ),
+            Err(HttpError::TooLarge)
+        );
+        assert_eq!(
+            parse_request(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n", 16),
+            Err(HttpError::BadRequest)
+        );
+        assert_eq!(
+            parse_request(b"GET example.com HTTP/1.1\r\n\r\n", 16),
+            Err(HttpError::BadRequest)
+        );
+        assert_eq!(
+            parse_request(b"GET / SPDY\r\n\r\n", 16),
+            Err(HttpError::BadRequest)
+        );
+        assert_eq!(
+            parse_request(b"GET / HTTP/1.1\r\nBroken\r\n\r\n", 16),
+            Err(HttpError::BadRequest)
+        );
+        assert_eq!(parse_request(b"\r\n\r\n", 16), Err(HttpError::BadRequest));
+    }
+
+    #[test]
+    fn test_route() {
+        let r = |head: &[u8]| route(&parse_request(head, 16).unwrap().unwrap());
+        assert_eq!(r(b"GET / HTTP/1.1\r\n\r\n"), Route::Index);
+        assert_eq!(r(b"GET /api/config HTTP/1.1\r\n\r\n"), Route::GetConfig);
+        assert_eq!(r(b"POST /api/config HTTP/1.1\r\n\r\n"), Route::PostConfig);
+        assert_eq!(
+            r(b"DELETE /api/config HTTP/1.1\r\n\r\n"),
+            Route::MethodNotAllowed
+        );
+        assert_eq!(r(b"POST / HTTP/1.1\r\n\r\n"), Route::MethodNotAllowed);
+        // Captive portal checks of the OSes
+        assert_eq!(r(b"GET /generate_204 HTTP/1.1\r\n\r\n"), Route::Redirect);
+        assert_eq!(
+            r(b"GET /hotspot-detect.html HTTP/1.0\r\n\r\n"),
+            Route::Redirect
+        );
+        assert_eq!(r(b"POST /other HTTP/1.1\r\n\r\n"), Route::MethodNotAllowed);
+    }
+
+    #[test]
+    fn test_response_head() {
+        let head = response_head::<256>(200, "text/plain", 5, None);
+        assert_eq!(
+            head.as_str(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n"
+        );
+        let head = response_head::<256>(302, "text/plain", 0, Some("http://192.168.4.1/"));
+        assert!(head.starts_with("HTTP/1.1 302 Found\r\nLocation: http://192.168.4.1/\r\n"));
+    }
+}
@@ -0,0 +1,399 @@
+//! Provisioning mode, the board starts a WiFi access point and serves a config form at
+//! `http://192.168.4.1/` when there is no valid config.
+
+mod dhcp;
+mod dns;
+pub mod http;
+
+use core::fmt::Write as _;
+
+use embassy_executor::Spawner;
+use embassy_futures::select::{Either, select};
+use embassy_net::{
+    Config, Ipv4Address, Ipv4Cidr, Runner, Stack, StackResources, StaticConfigV4,
+    driver::{Driver, HardwareAddress},
+    tcp::TcpSocket,
+    udp::{PacketMetadata, UdpSocket},
+};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, once_lock::OnceLock, signal::Signal,
+};
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_io_async::Write;
+use esp_radio::wifi::{
+    AccessPointConfig, AuthMethod, ClientConfig, ModeConfig, WifiController, WifiDevice, WifiEvent,
+};
+use log::{debug, info, warn};
+use serde::{Deserialize, de::IgnoredAny};
+
+use crate::{
+    AppConfig, ConfigStore, IndicatorStatus,
+    config::{ConfigStoreError, MAX_CONFIG_SIZE},
+    constants::*,
+    mk_static, set_indicator_status,
+};
+
+use http::{Route, parse_request, response_head, route};
+
+const PORTAL_ADDRESS: [u8; 4] = [192, 168, 4, 1];
+const PORTAL_URL: &str = "http://192.168.4.1/";
+const INDEX_HTML: &[u8] = include_bytes!("index.html");
+// The config and the request line and headers
+const MAX_REQUEST_SIZE: usize = MAX_CONFIG_SIZE + 1024;
+// SSID of the compile-time default config, which is just a placeholder
+const PLACEHOLDER_SSID: &str = "my-ssid";
+
+// Set before rebooting into provisioning mode, the RTC fast memory survives the software reset
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut PROVISIONING_REQUEST: u32 = 0;
+const PROVISIONING_MAGIC: u32 = 0x5052_4F56;
+
+static AP_SSID: OnceLock<heapless::String<32>> = OnceLock::new();
+
+// How long joining the network with new WiFi settings may take
+const WIFI_JOIN_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// WiFi settings to be tried before they are saved
+pub struct WifiSettings {
+    pub ssid: heapless::String<32>,
+    pub password: heapless::String<64>,
+}
+
+static WIFI_SETTINGS_REQUEST: Signal<CriticalSectionRawMutex, WifiSettings> = Signal::new();
+static WIFI_SETTINGS_RESULT: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Try to join the network with the settings, returns `true` if it worked.
+///
+/// The task that owns the WiFi controller tries them, the current connection is dropped meanwhile.
+pub async fn try_wifi_settings(settings: WifiSettings) -> bool {
+    WIFI_SETTINGS_RESULT.reset();
+    WIFI_SETTINGS_REQUEST.signal(settings);
+    // Give the task time to finish what it was doing with the controller first
+    with_timeout(WIFI_JOIN_TIMEOUT * 2, WIFI_SETTINGS_RESULT.wait())
+        .await
+        .unwrap_or(false)
+}
+
+/// Wait for the next `try_wifi_settings`, to be answered with `answer_wifi_settings`.
+pub async fn wait_wifi_settings() -> WifiSettings {
+    WIFI_SETTINGS_REQUEST.wait().await
+}
+
+/// Join the network with the settings and leave it again, the controller is stopped afterwards,
+/// so the caller must set its own config again. `ap` keeps the access point up meanwhile.
+pub async fn answer_wifi_settings(
+    controller: &mut WifiController<'static>,
+    settings: WifiSettings,
+    ap: Option<&AccessPointConfig>,
+) {
+    info!("Trying to join WiFi '{}'", settings.ssid);
+    let client = ClientConfig::default()
+        .with_ssid(settings.ssid.as_str().into())
+        .with_password(settings.password.as_str().into());
+    let mode = match ap {
+        Some(ap) => ModeConfig::ApSta(client, ap.clone()),
+        None => ModeConfig::Client(client),
+    };
+    controller.stop_async().await.ok();
+    let joined = match controller.set_config(&mode) {
+        Ok(_) if controller.start_async().await.is_ok() => {
+            matches!(
+                with_timeout(WIFI_JOIN_TIMEOUT, controller.connect_async()).await,
+                Ok(Ok(_))
+            )
+        }
+        _ => false,
+    };
+    info!("Joining WiFi '{}': {joined}", settings.ssid);
+    controller.disconnect_async().await.ok();
+    controller.stop_async().await.ok();
+    WIFI_SETTINGS_RESULT.signal(joined);
+}
+
+/// Reboot into provisioning mode, the current config is kept until a new one is submitted.
+pub fn request_provisioning() -> ! {
+    info!("Rebooting into provisioning mode...");
+    unsafe { PROVISIONING_REQUEST = PROVISIONING_MAGIC };
+    esp_hal::system::software_reset()
+}
+
+/// Provisioning is needed if it was requested before the reboot, or the config in flash is not
+/// valid and the firmware is not built with the WiFi settings.
+pub fn is_provisioning_required() -> bool {
+    let requested = unsafe { PROVISIONING_REQUEST } == PROVISIONING_MAGIC;
+    unsafe { PROVISIONING_REQUEST = 0 };
+    requested || (!AppConfig::is_loaded() && AppConfig::get().ssid == PLACEHOLDER_SSID)
+}
+
+/// SSID of the provisioning access point, available after provisioning is started
+pub fn get_ap_ssid() -> Option<&'static str> {
+    AP_SSID.try_get().map(|s| s.as_str())
+}
+
+/// Start the access point and serve the config form until a valid config is submitted, then
+/// reboot.
+pub async fn start_provisioning(
+    spawner: Spawner,
+    mut controller: WifiController<'static>,
+    device: WifiDevice<'static>,
+    seed: u64,
+) -> ! {
+    let mac = match device.hardware_address() {
+        HardwareAddress::Ethernet(mac) => mac,
+        _ => [0; 6],
+    };
+    let mut ssid = heapless::String::<32>::new();
+    write!(ssid, "Esparrier-{:02X}{:02X}", mac[4], mac[5]).ok();
+    let ssid = AP_SSID.get_or_init(|| ssid);
+    info!("Starting provisioning access point '{ssid}'");
+
+    let mut ap_config = AccessPointConfig::default().with_ssid(ssid.as_str().into());
+    if !PROVISIONING_PASSWORD.is_empty() {
+        ap_config = ap_config
+            .with_auth_method(AuthMethod::Wpa2Personal)
+            .with_password(PROVISIONING_PASSWORD.into());
+    }
+    controller
+        .set_config(&ModeConfig::AccessPoint(ap_config))
+        .unwrap();
+    controller.start_async().await.unwrap();
+    spawner.must_spawn(ap_task(controller, ap_config));
+
+    let [a, b, c, d] = PORTAL_ADDRESS;
+    let (stack, runner) = embassy_net::new(
+        device,
+        Config::ipv4_static(StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(a, b, c, d), 24),
+            gateway: None,
+            dns_servers: Default::default(),
+        }),
+        // DHCP, DNS and HTTP sockets
+        mk_static!(StackResources<3>, StackResources::<3>::new()),
+        seed,
+    );
+    spawner.must_spawn(net_task(runner));
+    spawner.must_spawn(dhcp_task(stack));
+    spawner.must_spawn(dns_task(stack));
+
+    set_indicator_status(IndicatorStatus::Provisioning).await;
+    info!("Provisioning portal is at {PORTAL_URL}");
+
+    let mut rx_buffer = [0; 1536];
+    let mut tx_buffer = [0; 1536];
+    let mut request = [0; MAX_REQUEST_SIZE];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if let Err(e) = socket.accept(80).await {
+            warn!("Failed to accept HTTP connection: {e:?}");
+            continue;
+        }
+        let committed = handle_connection(&mut socket, &mut request).await;
+        socket.flush().await.ok();
+        socket.close();
+        if committed {
+            // Let the response go out before rebooting
+            Timer::after(Duration::from_millis(500)).await;
+            info!("Config saved, rebooting...");
+            esp_hal::system::software_reset();
+        }
+    }
+}
+
+/// Handle one request, returns `true` if a new config is committed.
+async fn handle_connection(socket: &mut TcpSocket<'_>, buf: &mut [u8]) -> bool {
+    let mut len = 0;
+    // Read until the whole request is received
+    loop {
+        match socket.read(&mut buf[len..]).await {
+            Ok(0) | Err(_) => return false,
+            Ok(n) => len += n,
+        }
+        match parse_request(&buf[..len], MAX_CONFIG_SIZE) {
+            Ok(Some(request)) if len >= request.len() => break,
+            Ok(_) if len < buf.len() => continue,
+            Ok(_) => {
+                respond(socket, 413, "text/plain", b"", None).await;
+                return false;
+            }
+            Err(e) => {
+                respond(socket, e.status(), "text/plain", b"", None).await;
+                return false;
+            }
+        }
+    }
+    let Ok(Some(request)) = parse_request(&buf[..len], MAX_CONFIG_SIZE) else {
+        return false;
+    };
+    debug!("HTTP {:?} {}", request.method, request.path);
+
+    match route(&request) {
+        Route::Index => respond(socket, 200, "text/html", INDEX_HTML, None).await,
+        Route::GetConfig => {
+            let config = ConfigStore::current();
+            let body = &config.data[..config.len()];
+            respond(socket, 200, "application/json", body, None).await;
+        }
+        Route::PostConfig => {
+            let result = match posted_config(&buf[request.header_len..request.len()]) {
+                Ok(mut config) => match config.validate() {
+                    Ok(_) => config.commit().await,
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => {
+                    respond(socket, 200, "application/json", b"{\"ok\":true}", None).await;
+                    return true;
+                }
+                Err(e) => {
+                    warn!("Invalid config submitted: {e:?}");
+                    respond(
+                        socket,
+                        400,
+                        "application/json",
+                        b"{\"error\":\"invalid config\"}",
+                        None,
+                    )
+                    .await;
+                }
+            }
+        }
+        Route::Redirect => respond(socket, 302, "text/plain", b"", Some(PORTAL_URL)).await,
+        Route::MethodNotAllowed => respond(socket, 405, "text/plain", b"", None).await,
+    }
+    false
+}
+
+/// The submitted config, the form never shows the stored password so a config without one keeps
+/// it.
+fn posted_config(body: &[u8]) -> Result<ConfigStore, ConfigStoreError> {
+    #[derive(Deserialize)]
+    struct Password {
+        password: Option<IgnoredAny>,
+    }
+    let mut config = ConfigStore::new();
+    let (posted, _) = serde_json_core::from_slice::<Password>(body)?;
+    if posted.password.is_some() {
+        config.write_block(0, body);
+        return Ok(config);
+    }
+    // Insert the stored password as the first field
+    let mut password = [0; 400];
+    let len = serde_json_core::to_slice(&AppConfig::get().password, &mut password)?;
+    let start = body
+        .iter()
+        .position(|c| *c == b'{')
+        .ok_or(ConfigStoreError::SerdeError)?
+        + 1;
+    let rest = &body[start..];
+    let separator: &[u8] = match rest.iter().find(|c| !c.is_ascii_whitespace()) {
+        Some(b'}') => b"",
+        _ => b",",
+    };
+    let prefix: &[u8] = b"{\"password\":";
+    if prefix.len() + len + separator.len() + rest.len() > MAX_CONFIG_SIZE {
+        return Err(ConfigStoreError::RangeTooLarge);
+    }
+    for part in [prefix, &password[..len], separator, rest] {
+        config.write_block(config.len(), part);
+    }
+    Ok(config)
+}
+
+async fn respond(
+    socket: &mut TcpSocket<'_>,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+    location: Option<&str>,
+) {
+    let head = response_head::<256>(status, content_type, body.len(), location);
+    if socket.write_all(head.as_bytes()).await.is_err() || socket.write_all(body).await.is_err() {
+        warn!("Failed to send HTTP response");
+    }
+}
+
+#[embassy_executor::task]
+async fn ap_task(mut controller: WifiController<'static>, ap_config: AccessPointConfig) {
+    loop {
+        match select(
+            controller.wait_for_event(WifiEvent::ApStaConnected),
+            wait_wifi_settings(),
+        )
+        .await
+        {
+            Either::First(_) => info!("A station joined the provisioning access point"),
+            Either::Second(settings) => {
+                answer_wifi_settings(&mut controller, settings, Some(&ap_config)).await;
+                controller
+                    .set_config(&ModeConfig::AccessPoint(ap_config.clone()))
+                    .ok();
+                controller.start_async().await.ok();
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn dhcp_task(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(67).unwrap();
+    let mut server = dhcp::DhcpServer::new(PORTAL_ADDRESS);
+    let mut request = [0; 576];
+    let mut reply = [0; 576];
+    loop {
+        let Ok((n, _)) = socket.recv_from(&mut request).await else {
+            continue;
+        };
+        if let Some(len) = server.reply(&request[..n], &mut reply) {
+            // The client has no address yet
+            socket
+                .send_to(&reply[..len], (Ipv4Address::BROADCAST, 68))
+                .await
+                .ok();
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn dns_task(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(53).unwrap();
+    let mut query = [0; 512];
+    let mut reply = [0; 512];
+    loop {
+        let Ok((n, meta)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        if let Some(len) = dns::reply(&query[..n], PORTAL_ADDRESS, &mut reply) {
+            socket.send_to(&reply[..len], meta.endpoint).await.ok();
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use embassy_time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::BarrierError;
+
+const DEFAULT_INITIAL_DELAY: u32 = 1000;
+const DEFAULT_MAX_DELAY: u32 = 60000;
+const DEFAULT_JITTER: u8 = 20;
+const DEFAULT_FAST_RETRY_DELAY: u32 = 500;
+const DEFAULT_BUSY_DELAY: u32 = 10000;
+const DEFAULT_MIN_SESSION: u32 = 10000;
+
+/// How long to wait before reconnecting to the Barrier server, all delays are in milliseconds.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ReconnectPolicy {
+    // Delay after the first failure, doubled after each consecutive failure
+    #[serde(default = "get_default_initial_delay")]
+    pub initial_delay: u32,
+    #[serde(default = "get_default_max_delay")]
+    pub max_delay: u32,
+    // Randomize each delay by up to this percentage, so devices don't reconnect all at once
+    #[serde(default = "get_default_jitter")]
+    pub jitter: u8,
+    // Delay after the server closed the session with a goodbye, e.g. the server was restarted
+    #[serde(default = "get_default_fast_retry_delay")]
+    pub fast_retry_delay: u32,
+    // Delay after the server reported busy, the server needs a while to drop the stale session
+    #[serde(default = "get_default_busy_delay")]
+    pub busy_delay: u32,
+    // A session must last this long to reset the backoff, e.g. a server that drops the connection
+    // right after accepting it is retried with growing delays
+    #[serde(default = "get_default_min_session")]
+    pub min_session: u32,
+}
+
+fn get_default_initial_delay() -> u32 {
+    DEFAULT_INITIAL_DELAY
+}
+
+fn get_default_max_delay() -> u32 {
+    DEFAULT_MAX_DELAY
+}
+
+fn get_default_jitter() -> u8 {
+    DEFAULT_JITTER
+}
+
+fn get_default_fast_retry_delay() -> u32 {
+    DEFAULT_FAST_RETRY_DELAY
+}
+
+fn get_default_busy_delay() -> u32 {
+    DEFAULT_BUSY_DELAY
+}
+
+fn get_default_min_session() -> u32 {
+    DEFAULT_MIN_SESSION
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: DEFAULT_INITIAL_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            jitter: DEFAULT_JITTER,
+            fast_retry_delay: DEFAULT_FAST_RETRY_DELAY,
+            busy_delay: DEFAULT_BUSY_DELAY,
+            min_session: DEFAULT_MIN_SESSION,
+        }
+    }
+}
+
+/// What to do after a Barrier session ended
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectDecision {
+    pub delay: Duration,
+    // Fail over to the next server instead of retrying the current one
+    pub next_server: bool,
+}
+
+/// Tracks consecutive failures and decides the delay of the next reconnection
+pub struct Reconnect<'a> {
+    policy: &'a ReconnectPolicy,
+    failures: u32,
+}
+
+impl<'a> Reconnect<'a> {
+    pub fn new(policy: &'a ReconnectPolicy) -> Self {
+        Self {
+            policy,
+            failures: 0,
+        }
+    }
+
+    /// Decide what to do with the `result` returned by `start_barrier_client` after a session of
+    /// `session` length, zero if it never started, `random` is used to apply the jitter.
+    pub fn next(
+        &mut self,
+        result: &Result<(), BarrierError>,
+        session: Duration,
+        random: u32,
+    ) -> ReconnectDecision {
+        let working = session >= Duration::from_millis(self.policy.min_session as u64);
+        let (delay, next_server) = match result {
+            // Clean goodbye, the server is probably restarting
+            Ok(()) if working => {
+                self.failures = 0;
+                (self.policy.fast_retry_delay, false)
+            }
+            // The session was working, the connection just dropped
+            Err(BarrierError::Disconnected) if working => {
+                self.failures = 0;
+                (self.policy.initial_delay, false)
+            }
+            // The server accepts the connection but doesn't keep it
+            Ok(()) | Err(BarrierError::Disconnected) => {
+                self.failures += 1;
+                (self.backoff(), false)
+            }
+            // Another session with the same screen name is still alive on this server
+            Err(BarrierError::ServerBusy) => {
+                self.failures += 1;
+                (self.policy.busy_delay.max(self.backoff()), false)
+            }
+            // Misconfiguration, not going to be fixed by retrying soon
+            Err(BarrierError::UnknownDevice) | Err(BarrierError::IncompatibleVersion(..)) => {
+                self.failures += 1;
+                (self.policy.max_delay, true)
+            }
+            // The server is unreachable or not talking Barrier
+            Err(BarrierError::TcpError)
+            | Err(BarrierError::ProtocolError(_))
+            | Err(BarrierError::BadProtocol) => {
+                self.failures += 1;
+                (self.backoff(), true)
+            }
+        };
+        let delay = self.apply_jitter(delay, random).min(self.policy.max_delay);
+        ReconnectDecision {
+            delay: Duration::from_millis(delay as u64),
+            next_server,
+        }
+    }
+
+    fn backoff(&self) -> u32 {
+        let exp = self.failures.saturating_sub(1).min(31);
+        self.policy
+            .initial_delay
+            .saturating_mul(1 << exp)
+            .min(self.policy.max_delay)
+    }
+
+    fn apply_jitter(&self, delay: u32, random: u32) -> u32 {
+        let jitter = self.policy.jitter.min(100) as u64;
+        if jitter == 0 {
+            return delay;
+        }
+        // Scale the delay by (100 - jitter)% to (100 + jitter)%
+        let percent = 100 - jitter + (random as u64 % (jitter * 2 + 1));
+        (delay as u64 * percent / 100) as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Duration;
+
+    use super::{Reconnect, ReconnectPolicy};
+    use crate::BarrierError;
+
+    // Long enough to count as a working session
+    const SESSION: Duration = Duration::from_secs(60);
+
+    fn with_jitter(jitter: u8) -> ReconnectPolicy {
+        ReconnectPolicy {
+            jitter,
+            ..ReconnectPolicy::default()
+        }
+    }
+
+    fn next(reconnect: &mut Reconnect, result: Result<(), BarrierError>) -> (u64, bool) {
+        let decision = reconnect.next(&result, SESSION, 0);
+        (decision.delay.as_millis(), decision.next_server)
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = with_jitter(0);
+        let mut reconnect = Reconnect::new(&policy);
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::TcpError)),
+            (1000, true)
+        );
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::BadProtocol)),
+            (2000, true)
+        );
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::TcpError)),
+            (4000, true)
+        );
+        for _ in 0..40 {
+            next(&mut reconnect, Err(BarrierError::TcpError));
+        }
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::TcpError)),
+            (60000, true)
+        );
+        // A working session resets the backoff
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::Disconnected)),
+            (1000, false)
+        );
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::TcpError)),
+            (1000, true)
+        );
+        assert_eq!(next(&mut reconnect, Ok(())), (500, false));
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::TcpError)),
+            (1000, true)
+        );
+    }
+
+    #[test]
+    fn test_short_sessions() {
+        let policy = with_jitter(0);
+        let mut reconnect = Reconnect::new(&policy);
+        let short = Duration::from_secs(1);
+        // Dropped right after connecting, backs off without failing over
+        for delay in [1000, 2000, 4000] {
+            let decision = reconnect.next(&Err(BarrierError::Disconnected), short, 0);
+            assert_eq!(
+                (decision.delay.as_millis(), decision.next_server),
+                (delay, false)
+            );
+        }
+        let decision = reconnect.next(&Ok(()), Duration::from_millis(0), 0);
+        assert_eq!(decision.delay.as_millis(), 8000);
+        // A long enough session resets the backoff
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::Disconnected)),
+            (1000, false)
+        );
+    }
+
+    #[test]
+    fn test_server_errors() {
+        let policy = with_jitter(0);
+        let mut reconnect = Reconnect::new(&policy);
+        // Busy stays on the server, and waits longer once the backoff grows past the busy delay
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::ServerBusy)),
+            (10000, false)
+        );
+        for _ in 0..4 {
+            next(&mut reconnect, Err(BarrierError::ServerBusy));
+        }
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::ServerBusy)),
+            (32000, false)
+        );
+        // Misconfigurations fail over with the longest delay
+        assert_eq!(
+            next(&mut reconnect, Err(BarrierError::UnknownDevice)),
+            (60000, true)
+        );
+        let result = Err(BarrierError::IncompatibleVersion(2, 0));
+        assert_eq!(next(&mut reconnect, result), (60000, true));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let policy = with_jitter(20);
+        let mut reconnect = Reconnect::new(&policy);
+        let result = Err(BarrierError::Disconnected);
+        let delay = |reconnect: &mut Reconnect, random| {
+            reconnect.next(&result, SESSION, random).delay.as_millis()
+        };
+        // 80% to 120% of the delay
+        assert_eq!(delay(&mut reconnect, 0), 800);
+        assert_eq!(delay(&mut reconnect, 20), 1000);
+        assert_eq!(delay(&mut reconnect, 40), 1200);
+        assert_eq!(delay(&mut reconnect, 41), 800);
+        // Out of range jitter is clamped, the delay is never negative
+        let policy = with_jitter(200);
+        let mut reconnect = Reconnect::new(&policy);
+        assert_eq!(delay(&mut reconnect, 0), 0);
+        assert_eq!(delay(&mut reconnect, 200), 2000);
+        // The jitter never takes the delay past the maximum
+        let policy = with_jitter(20);
+        let mut reconnect = Reconnect::new(&policy);
+        let result = Err(BarrierError::UnknownDevice);
+        assert_eq!(
+            reconnect.next(&result, SESSION, 40).delay.as_millis(),
+            60000
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Why the board was reset, kept across resets in the RTC fast memory, which is only cleared on
+//! power-on.
+//!
+//! The hardware reset reason tells a watchdog or a brownout from a software reset, and before
+//! resetting the board itself the firmware records why, e.g. a panic and its location, together
+//! with the last indicator status.
+
+use core::fmt::Write;
+
+use embassy_sync::once_lock::OnceLock;
+use esp_hal::rtc_cntl::SocResetReason;
+use heapless::String;
+use log::{info, warn};
+
+use crate::{IndicatorStatus, running_state::get_running_state_mut};
+
+// "ESPR", anything else means the record was never written since power-on
+const MAGIC: u32 = 0x4553_5052;
+pub(crate) const MAX_LOCATION_LENGTH: usize = 48;
+pub(crate) const MAX_MESSAGE_LENGTH: usize = 112;
+
+/// Why the firmware reset the board itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ResetCause {
+    // Nothing recorded, e.g. power-on, a reboot on request, or a watchdog reset of a hung firmware
+    None = 0,
+    Panic = 1,
+    // Writing a HID report timed out, the USB stack is stalled
+    HidTimeout = 2,
+    // A task missed its health check deadline, the message is its name
+    TaskStuck = 3,
+}
+
+impl ResetCause {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Panic,
+            2 => Self::HidTimeout,
+            3 => Self::TaskStuck,
+            _ => Self::None,
+        }
+    }
+}
+
+#[repr(C)]
+struct Record {
+    magic: u32,
+    // Resets since power-on
+    resets: u32,
+    // Resets since power-on caused by a panic, a watchdog, a brownout, etc.
+    unexpected_resets: u32,
+    // Resets since power-on caused by a stalled HID report write
+    hid_report_timeouts: u32,
+    cause: u8,
+    indicator: u8,
+    location_len: u8,
+    message_len: u8,
+    line: u32,
+    location: [u8; MAX_LOCATION_LENGTH],
+    message: [u8; MAX_MESSAGE_LENGTH],
+}
+
+impl Record {
+    const EMPTY: Self = Self {
+        magic: 0,
+        resets: 0,
+        unexpected_resets: 0,
+        hid_report_timeouts: 0,
+        cause: 0,
+        indicator: 0,
+        location_len: 0,
+        message_len: 0,
+        line: 0,
+        location: [0; MAX_LOCATION_LENGTH],
+        message: [0; MAX_MESSAGE_LENGTH],
+    };
+}
+
+// Only integers and arrays of them, any bit pattern is valid
+unsafe impl esp_hal::Persistable for Record {}
+
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut RECORD: Record = Record::EMPTY;
+
+fn with_record<R>(f: impl FnOnce(&mut Record) -> R) -> R {
+    // SAFETY: Only accessed inside a critical section, on a single core
+    critical_section::with(|_| f(unsafe { &mut *core::ptr::addr_of_mut!(RECORD) }))
+}
+
+/// The last reset, read at boot
+#[derive(Clone, Debug)]
+pub struct ResetReport {
+    // Hardware reset reason, `None` if unknown
+    pub reason: Option<SocResetReason>,
+    pub cause: ResetCause,
+    // `IndicatorStatus::code` of the last status shown, 0 if none
+    pub indicator: u8,
+    // Where the panic happened
+    pub location: String<MAX_LOCATION_LENGTH>,
+    pub line: u32,
+    pub message: String<MAX_MESSAGE_LENGTH>,
+}
+
+impl ResetReport {
+    fn is_unexpected(&self) -> bool {
+        self.cause != ResetCause::None
+            || !matches!(
+                self.reason,
+                Some(SocResetReason::ChipPowerOn)
+                    | Some(SocResetReason::CoreSw)
+                    | Some(SocResetReason::CpuSw)
+                    | Some(SocResetReason::CoreUsbUart)
+                    | Some(SocResetReason::CoreUsbJtag)
+            )
+    }
+
+    /// Reason (1 byte, 0 if unknown), cause (1 byte), indicator (1 byte), line (4 bytes LE),
+    /// location length (1 byte) and message length (1 byte), the location and the message follow
+    pub fn to_bytes<'a>(&self, bytes: &'a mut [u8]) -> &'a [u8] {
+        bytes[0] = self.reason.map(|r| r as u8).unwrap_or_default();
+        bytes[1] = self.cause as u8;
+        bytes[2] = self.indicator;
+        bytes[3..7].copy_from_slice(&self.line.to_le_bytes());
+        bytes[7] = self.location.len() as u8;
+        bytes[8] = self.message.len() as u8;
+        &bytes[..9]
+    }
+}
+
+static LAST_RESET: OnceLock<ResetReport> = OnceLock::new();
+
+/// Read and clear the record left by the last reset, and count the resets.
+pub async fn init_reset_report() {
+    let reason = esp_hal::system::reset_reason();
+    let (report, resets, unexpected_resets, hid_report_timeouts) = with_record(|record| {
+        if record.magic != MAGIC || reason == Some(SocResetReason::ChipPowerOn) {
+            *record = Record {
+                magic: MAGIC,
+                ..Record::EMPTY
+            };
+        } else {
+            record.resets = record.resets.wrapping_add(1);
+        }
+        let report = ResetReport {
+            reason,
+            cause: ResetCause::from_u8(record.cause),
+            indicator: record.indicator,
+            location: to_string(&record.location, record.location_len),
+            line: record.line,
+            message: to_string(&record.message, record.message_len),
+        };
+        if report.is_unexpected() {
+            record.unexpected_resets = record.unexpected_resets.wrapping_add(1);
+        }
+        if report.cause == ResetCause::HidTimeout {
+            record.hid_report_timeouts = record.hid_report_timeouts.wrapping_add(1);
+        }
+        record.cause = ResetCause::None as u8;
+        record.location_len = 0;
+        record.message_len = 0;
+        record.line = 0;
+        (
+            report,
+            record.resets,
+            record.unexpected_resets,
+            record.hid_report_timeouts,
+        )
+    });
+
+    if report.is_unexpected() {
+        warn!(
+            "Last reset: {:?}, {:?} {}:{} {}",
+            report.reason, report.cause, report.location, report.line, report.message
+        );
+    } else {
+        info!("Last reset: {:?}", report.reason);
+    }
+    {
+        let stats = &mut get_running_state_mut().await.stats;
+        stats.resets = resets;
+        stats.unexpected_resets = unexpected_resets;
+        stats.hid_report_timeouts = hid_report_timeouts;
+    }
+    LAST_RESET.init(report).ok();
+}
+
+pub fn get_reset_report() -> Option<&'static ResetReport> {
+    LAST_RESET.try_get()
+}
+
+pub(crate) fn record_indicator_status(status: &IndicatorStatus) {
+    with_record(|record| record.indicator = status.code());
+}
+
+/// Record why the board is about to be reset, e.g. by the watchdog
+pub(crate) fn record_reset_cause(cause: ResetCause, message: &str) {
+    with_record(|record| {
+        record.cause = cause as u8;
+        record.location_len = 0;
+        record.line = 0;
+        record.message_len = copy_str(&mut record.message, message);
+    });
+}
+
+/// Record why the board is reset, and reset it
+pub(crate) fn reset(cause: ResetCause, message: &str) -> ! {
+    record_reset_cause(cause, message);
+    esp_hal::system::software_reset()
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    esp_println::println!("\n\n====================== PANIC ======================");
+    esp_println::println!("{info}");
+    with_record(|record| {
+        record.cause = ResetCause::Panic as u8;
+        match info.location() {
+            Some(location) => {
+                record.location_len = copy_str(&mut record.location, location.file());
+                record.line = location.line();
+            }
+            None => {
+                record.location_len = 0;
+                record.line = 0;
+            }
+        }
+        let mut writer = Truncate {
+            buf: &mut record.message,
+            len: 0,
+        };
+        write!(writer, "{}", info.message()).ok();
+        record.message_len = writer.len as u8;
+    });
+    esp_hal::system::software_reset()
+}
+
+fn copy_str(buf: &mut [u8], s: &str) -> u8 {
+    let mut writer = Truncate { buf, len: 0 };
+    writer.write_str(s).ok();
+    writer.len as u8
+}
+
+fn to_string<const N: usize>(buf: &[u8; N], len: u8) -> String<N> {
+    let bytes = &buf[..(len as usize).min(N)];
+    // The record may be cut in the middle of a character, or garbage after a brownout
+    let valid = match core::str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+    };
+    String::try_from(valid).unwrap_or_default()
+}
+
+/// Writes as much as fits in the buffer, the rest is dropped
+struct Truncate<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
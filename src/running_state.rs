@@ -1,7 +1,175 @@
-use embassy_net::Ipv4Cidr;
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use core::{
+    cell::Cell,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
-use crate::constants::*;
+use critical_section::Mutex as CsMutex;
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Cidr};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+use crate::{AppConfig, BarrierError, constants::*};
+
+/// Number of the most recent Barrier errors kept in the history
+pub const MAX_ERROR_HISTORY: usize = 8;
+
+/// A Barrier error and when it happened
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorRecord {
+    // Seconds since boot
+    pub uptime: u32,
+    pub code: u8,
+}
+
+impl ErrorRecord {
+    fn new(error: &BarrierError) -> Self {
+        Self {
+            uptime: Instant::now().as_secs() as u32,
+            code: match error {
+                BarrierError::Disconnected => b'd',
+                BarrierError::TcpError => b't',
+                BarrierError::ProtocolError(_) => b'p',
+                BarrierError::ServerBusy => b'b',
+                BarrierError::UnknownDevice => b'u',
+                BarrierError::IncompatibleVersion(..) => b'v',
+                BarrierError::BadProtocol => b'x',
+            },
+        }
+    }
+}
+
+/// Traffic counters since boot
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrafficStats {
+    pub bytes_in: u32,
+    pub bytes_out: u32,
+    pub packets_in: u32,
+    pub packets_out: u32,
+}
+
+// Counted by the packet stream as it goes, so the stats are up to date whenever they are read and
+// nothing is lost when a session ends early
+static TRAFFIC: CsMutex<Cell<TrafficStats>> = CsMutex::new(Cell::new(TrafficStats {
+    bytes_in: 0,
+    bytes_out: 0,
+    packets_in: 0,
+    packets_out: 0,
+}));
+
+pub fn get_traffic() -> TrafficStats {
+    critical_section::with(|cs| TRAFFIC.borrow(cs).get())
+}
+
+pub fn add_traffic(traffic: TrafficStats) {
+    critical_section::with(|cs| {
+        let cell = TRAFFIC.borrow(cs);
+        let mut total = cell.get();
+        total.bytes_in = total.bytes_in.wrapping_add(traffic.bytes_in);
+        total.bytes_out = total.bytes_out.wrapping_add(traffic.bytes_out);
+        total.packets_in = total.packets_in.wrapping_add(traffic.packets_in);
+        total.packets_out = total.packets_out.wrapping_add(traffic.packets_out);
+        cell.set(total);
+    });
+}
+
+/// Counters since boot, for troubleshooting unstable connections
+#[derive(Clone, Debug)]
+pub struct ConnectionStats {
+    pub connect_attempts: u32,
+    pub sessions: u32,
+    pub unknown_packets: u32,
+    // A timeout resets the board, so these are counted across resets since power-on
+    pub hid_report_timeouts: u32,
+    // Resets since power-on, and the ones not requested, e.g. panics and watchdog resets
+    pub resets: u32,
+    pub unexpected_resets: u32,
+    // Start of the current session, `None` if not connected
+    pub session_start: Option<Instant>,
+    // Most recent errors, oldest first
+    pub errors: Deque<ErrorRecord, MAX_ERROR_HISTORY>,
+}
+
+impl ConnectionStats {
+    pub const fn new() -> Self {
+        Self {
+            connect_attempts: 0,
+            sessions: 0,
+            unknown_packets: 0,
+            hid_report_timeouts: 0,
+            resets: 0,
+            unexpected_resets: 0,
+            session_start: None,
+            errors: Deque::new(),
+        }
+    }
+
+    pub fn session_started(&mut self) {
+        self.sessions = self.sessions.wrapping_add(1);
+        self.session_start = Some(Instant::now());
+    }
+
+    /// End the current session, returns its length, zero if it never started
+    pub fn session_ended(&mut self) -> Duration {
+        self.session_start
+            .take()
+            .map(|start| start.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Length of the current session in seconds, 0 if not connected
+    pub fn session_length(&self) -> u32 {
+        self.session_start
+            .map(|start| start.elapsed().as_secs() as u32)
+            .unwrap_or_default()
+    }
+
+    pub fn record_error(&mut self, error: &BarrierError) {
+        if self.errors.is_full() {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(ErrorRecord::new(error)).ok();
+    }
+
+    pub fn to_bytes<'a>(&self, bytes: &'a mut [u8]) -> &'a [u8] {
+        let traffic = get_traffic();
+        let values = [
+            Instant::now().as_secs() as u32,
+            self.connect_attempts,
+            self.sessions,
+            self.session_length(),
+            traffic.bytes_in,
+            traffic.bytes_out,
+            traffic.packets_in,
+            traffic.packets_out,
+            self.unknown_packets,
+            self.hid_report_timeouts,
+            self.resets,
+            self.unexpected_resets,
+        ];
+        for (i, value) in values.iter().enumerate() {
+            bytes[i * 4..i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        &bytes[..values.len() * 4]
+    }
+
+    /// Error history, newest first, each record is the uptime (4 bytes LE) and the error code
+    pub fn errors_to_bytes<'a>(&self, bytes: &'a mut [u8]) -> &'a [u8] {
+        bytes[0] = self.errors.len() as u8;
+        for (i, record) in self.errors.iter().rev().enumerate() {
+            let offset = 1 + i * 5;
+            bytes[offset..offset + 4].copy_from_slice(&record.uptime.to_le_bytes());
+            bytes[offset + 4] = record.code;
+        }
+        &bytes[..1 + self.errors.len() * 5]
+    }
+}
+
+impl Default for ConnectionStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct RunningState {
@@ -14,6 +182,11 @@ pub struct RunningState {
     pub active: bool,
     pub keep_awake: bool,
     pub model_id: u8,
+    // The Barrier server currently being used
+    pub server_endpoint: Option<IpEndpoint>,
+    // Progress of the clipboard typing in percent, `None` if not typing
+    pub typing_progress: Option<u8>,
+    pub stats: ConnectionStats,
 }
 
 impl RunningState {
@@ -28,6 +201,9 @@ impl RunningState {
             active: false,
             keep_awake: false,
             model_id: MODEL_ID,
+            server_endpoint: None,
+            typing_progress: None,
+            stats: ConnectionStats::new(),
         }
     }
 
@@ -57,8 +233,19 @@ impl RunningState {
         bytes[10] = self.active as u8;
         bytes[11] = self.keep_awake as u8;
         bytes[12] = self.model_id;
+        match self.server_endpoint {
+            Some(IpEndpoint {
+                addr: IpAddress::Ipv4(addr),
+                port,
+            }) => {
+                bytes[13..17].copy_from_slice(&addr.octets());
+                bytes[17..19].copy_from_slice(&port.to_le_bytes());
+            }
+            _ => bytes[13..19].fill(0),
+        }
+        bytes[19] = self.typing_progress.unwrap_or(0xFF);
 
-        &bytes[..13]
+        &bytes[..20]
     }
 }
 
@@ -82,3 +269,46 @@ pub async fn get_running_state_mut() -> embassy_sync::mutex::MutexGuard<
 > {
     RUNNING_STATE.lock().await
 }
+
+// Screen size set at runtime, overrides the config until next reboot, 0 means not set
+static SCREEN_SIZE: AtomicU32 = AtomicU32::new(0);
+static SCREEN_SIZE_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+pub fn get_screen_size() -> (u16, u16) {
+    match SCREEN_SIZE.load(Ordering::Relaxed) {
+        0 => (
+            AppConfig::get().screen_width,
+            AppConfig::get().screen_height,
+        ),
+        size => ((size >> 16) as u16, (size & 0xFFFF) as u16),
+    }
+}
+
+/// Change the screen size without rebooting, the Barrier client picks it up before handling the
+/// next packet and re-announces the screen to the server.
+pub fn set_screen_size(width: u16, height: u16) {
+    SCREEN_SIZE.store(((width as u32) << 16) | height as u32, Ordering::Relaxed);
+    SCREEN_SIZE_CHANGED.signal(());
+}
+
+/// Returns `true` once after each call to `set_screen_size`.
+pub fn screen_size_changed() -> bool {
+    SCREEN_SIZE_CHANGED.try_take().is_some()
+}
+
+static RECONNECT_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Drop the current Barrier session, the client connects again like after a goodbye.
+pub fn request_reconnect() {
+    RECONNECT_REQUESTED.signal(());
+}
+
+/// Returns `true` once after each call to `request_reconnect`.
+pub fn reconnect_requested() -> bool {
+    RECONNECT_REQUESTED.try_take().is_some()
+}
+
+/// Wait for the next call to `request_reconnect`, the request is taken like `reconnect_requested`.
+pub async fn wait_reconnect_requested() {
+    RECONNECT_REQUESTED.wait().await
+}
@@ -0,0 +1,193 @@
+use core::cmp::{max, min};
+
+use serde::{Deserialize, Serialize};
+
+/// Maximum value of the absolute X/Y axes in the HID report descriptor
+const HID_ABS_MAX: i64 = 0x7FFF;
+
+/// Maps the logical screen reported to the server into a region of the host's virtual desktop.
+///
+/// The absolute HID axes always cover the whole virtual desktop of the host, so when the host has
+/// more than one monitor, the cursor needs to be squeezed into the monitor the board is placed on.
+/// All sizes and positions are in host desktop pixels, zero values fall back to the logical screen.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ScreenMapping {
+    // Size of the host virtual desktop, covers all monitors
+    #[serde(default)]
+    pub desktop_width: u16,
+    #[serde(default)]
+    pub desktop_height: u16,
+
+    // Position and size of the target monitor inside the virtual desktop
+    #[serde(default)]
+    pub x: u16,
+    #[serde(default)]
+    pub y: u16,
+    #[serde(default)]
+    pub width: u16,
+    #[serde(default)]
+    pub height: u16,
+
+    // Clockwise rotation of the target monitor, 0, 90, 180 or 270 degrees
+    #[serde(default)]
+    pub rotation: u16,
+
+    // Calibration margins, positive values shrink the target region, negative values expand it
+    #[serde(default)]
+    pub margin_left: i16,
+    #[serde(default)]
+    pub margin_top: i16,
+    #[serde(default)]
+    pub margin_right: i16,
+    #[serde(default)]
+    pub margin_bottom: i16,
+}
+
+impl ScreenMapping {
+    /// Only right angles are supported
+    pub fn is_valid(&self) -> bool {
+        matches!(self.rotation, 0 | 90 | 180 | 270)
+    }
+
+    /// Convert the position on the logical screen with the size of `width` x `height` into the
+    /// absolute HID coordinates.
+    pub fn to_hid(&self, width: u16, height: u16, x: u16, y: u16) -> (u16, u16) {
+        let w = max(width, 1) as i64;
+        let h = max(height, 1) as i64;
+        let x = min(x as i64, w);
+        let y = min(y as i64, h);
+
+        // Position on the target monitor as (numerator, denominator) pairs
+        let ((nx, dx), (ny, dy)) = match self.rotation {
+            90 => ((h - y, h), (x, w)),
+            180 => ((w - x, w), (h - y, h)),
+            270 => ((y, h), (w - x, w)),
+            _ => ((x, w), (y, h)),
+        };
+        let (screen_w, screen_h) = match self.rotation {
+            90 | 270 => (h, w),
+            _ => (w, h),
+        };
+
+        let desktop_w = non_zero(self.desktop_width).unwrap_or(screen_w);
+        let desktop_h = non_zero(self.desktop_height).unwrap_or(screen_h);
+        let region_w = non_zero(self.width).unwrap_or(desktop_w);
+        let region_h = non_zero(self.height).unwrap_or(desktop_h);
+
+        let left = self.x as i64 + self.margin_left as i64;
+        let right = self.x as i64 + region_w - self.margin_right as i64;
+        let top = self.y as i64 + self.margin_top as i64;
+        let bottom = self.y as i64 + region_h - self.margin_bottom as i64;
+
+        let px = (left + (right - left) * nx / dx).clamp(0, desktop_w);
+        let py = (top + (bottom - top) * ny / dy).clamp(0, desktop_h);
+
+        (to_hid_axis(px, desktop_w), to_hid_axis(py, desktop_h))
+    }
+}
+
+fn non_zero(v: u16) -> Option<i64> {
+    if v == 0 { None } else { Some(v as i64) }
+}
+
+fn to_hid_axis(pos: i64, size: i64) -> u16 {
+    ((pos as u64 * HID_ABS_MAX as u64).div_ceil(size as u64) as i64).clamp(0, HID_ABS_MAX) as u16
+}
+
+#[cfg(test)]
+mod test {
+    use super::ScreenMapping;
+
+    #[test]
+    fn test_default_mapping() {
+        let mapping = ScreenMapping::default();
+        assert_eq!(mapping.to_hid(1920, 1080, 0, 0), (0, 0));
+        assert_eq!(mapping.to_hid(1920, 1080, 960, 540), (16384, 16384));
+        assert_eq!(mapping.to_hid(1920, 1080, 1920, 1080), (0x7FFF, 0x7FFF));
+        // Positions past the logical screen stay on its edge
+        assert_eq!(mapping.to_hid(1920, 1080, 5000, 5000), (0x7FFF, 0x7FFF));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let rotated = |rotation| ScreenMapping {
+            rotation,
+            ..Default::default()
+        };
+        // The top left corner of the logical screen, and a point right of it
+        let corners = |mapping: &ScreenMapping| {
+            (
+                mapping.to_hid(200, 100, 0, 0),
+                mapping.to_hid(200, 100, 100, 0),
+            )
+        };
+        assert_eq!(corners(&rotated(0)), ((0, 0), (16384, 0)));
+        assert_eq!(corners(&rotated(90)), ((0x7FFF, 0), (0x7FFF, 16384)));
+        assert_eq!(corners(&rotated(180)), ((0x7FFF, 0x7FFF), (16384, 0x7FFF)));
+        assert_eq!(corners(&rotated(270)), ((0, 0x7FFF), (0, 16384)));
+        // Unknown rotations are rejected by the config, and not rotated if they get here anyway
+        assert_eq!(corners(&rotated(45)), corners(&rotated(0)));
+    }
+
+    #[test]
+    fn test_valid_rotation() {
+        for rotation in [0, 90, 180, 270] {
+            let mapping = ScreenMapping {
+                rotation,
+                ..Default::default()
+            };
+            assert!(mapping.is_valid());
+        }
+        for rotation in [45, 360, 1] {
+            let mapping = ScreenMapping {
+                rotation,
+                ..Default::default()
+            };
+            assert!(!mapping.is_valid());
+        }
+    }
+
+    #[test]
+    fn test_region() {
+        // The right half of a 200x100 desktop
+        let mapping = ScreenMapping {
+            desktop_width: 200,
+            desktop_height: 100,
+            x: 100,
+            width: 100,
+            ..Default::default()
+        };
+        assert_eq!(mapping.to_hid(1000, 500, 0, 0), (16384, 0));
+        assert_eq!(mapping.to_hid(1000, 500, 1000, 500), (0x7FFF, 0x7FFF));
+    }
+
+    #[test]
+    fn test_margins() {
+        let mapping = ScreenMapping {
+            desktop_width: 200,
+            desktop_height: 100,
+            margin_left: 10,
+            margin_top: 10,
+            margin_right: 10,
+            margin_bottom: 10,
+            ..Default::default()
+        };
+        assert_eq!(mapping.to_hid(200, 100, 0, 0), (1639, 3277));
+        assert_eq!(mapping.to_hid(200, 100, 200, 100), (31129, 29491));
+
+        // Negative margins push the edges out of the desktop, the cursor stops at its edges
+        let mapping = ScreenMapping {
+            desktop_width: 200,
+            desktop_height: 100,
+            margin_left: -20,
+            margin_top: -20,
+            margin_right: -20,
+            margin_bottom: -20,
+            ..Default::default()
+        };
+        assert_eq!(mapping.to_hid(200, 100, 0, 0), (0, 0));
+        assert_eq!(mapping.to_hid(200, 100, 10, 10), (0, 0));
+        assert_eq!(mapping.to_hid(200, 100, 200, 100), (0x7FFF, 0x7FFF));
+        assert_eq!(mapping.to_hid(200, 100, 100, 50), (16384, 16384));
+    }
+}
@@ -0,0 +1,373 @@
+//! CDC-ACM serial port of the USB composite device.
+//!
+//! It's a line-oriented console, and on WiFi boards it also speaks the Improv Serial protocol so
+//! Improv tools and ESP Web Tools can set the WiFi up without WebUSB. Improv packets are picked
+//! out of the byte stream, everything else goes to the console.
+
+use core::fmt::Write as _;
+
+use embassy_time::Instant;
+use embassy_usb::class::cdc_acm::{CdcAcmClass, Sender};
+use esp_hal::otg_fs::asynch::Driver;
+use log::{info, warn};
+
+use crate::{
+    AppConfig, ConfigStore,
+    control::{reboot, set_keep_awake},
+    get_running_state,
+    logger::{get_log_level, set_log_level},
+    reset_report::get_reset_report,
+    running_state::{get_traffic, request_reconnect},
+    shell::{HELP, Input, LineBuffer, ShellCommand, ShellError, parse_line},
+};
+
+#[cfg(feature = "wifi")]
+use embassy_time::{Duration, Timer};
+
+#[cfg(feature = "wifi")]
+use crate::{
+    IndicatorStatus,
+    improv::{
+        Command, Feed, ImprovError, ImprovParser, ImprovState, PacketType, encode, parse_command,
+        rpc_result,
+    },
+    provisioning::{WifiSettings, get_ap_ssid, try_wifi_settings},
+    restore_indicator_status, set_indicator_status,
+};
+
+const MAX_PACKET_SIZE: usize = 64;
+const PROMPT: &str = "> ";
+// How long the indicator shows the identify pattern
+#[cfg(feature = "wifi")]
+const IDENTIFY_DURATION: Duration = Duration::from_secs(3);
+
+type SerialSender = Sender<'static, Driver<'static>>;
+type Output = heapless::String<1024>;
+
+#[embassy_executor::task]
+pub async fn serial_task(class: CdcAcmClass<'static, Driver<'static>>) {
+    let (mut sender, mut receiver) = class.split();
+    #[cfg(feature = "wifi")]
+    let mut improv = ImprovParser::default();
+    let mut line = LineBuffer::default();
+    let mut buf = [0; MAX_PACKET_SIZE];
+    loop {
+        receiver.wait_connection().await;
+        info!("Serial port opened");
+        write_all(
+            &mut sender,
+            b"\r\nEsparrier console, type 'help' for commands\r\n> ",
+        )
+        .await;
+        while let Ok(n) = receiver.read_packet(&mut buf).await {
+            for &b in &buf[..n] {
+                #[cfg(feature = "wifi")]
+                match improv.feed(b) {
+                    Feed::Packet(packet_type, data) if packet_type == PacketType::Rpc as u8 => {
+                        if let Err(e) = handle_improv_command(&mut sender, data).await {
+                            send_improv_error(&mut sender, e).await;
+                        }
+                    }
+                    Feed::Invalid => send_improv_error(&mut sender, ImprovError::InvalidRpc).await,
+                    Feed::Passthrough(bytes) => {
+                        for b in bytes {
+                            handle_input(&mut sender, &mut line, b).await;
+                        }
+                    }
+                    // Only the device sends other packets
+                    Feed::Packet(..) | Feed::Pending => {}
+                }
+                #[cfg(not(feature = "wifi"))]
+                handle_input(&mut sender, &mut line, b).await;
+            }
+        }
+        info!("Serial port closed");
+    }
+}
+
+async fn handle_input(sender: &mut SerialSender, line: &mut LineBuffer, byte: u8) {
+    match line.push(byte) {
+        Input::None => {}
+        Input::Echo(b) => write_all(sender, &[b]).await,
+        Input::Erase => write_all(sender, b"\x08 \x08").await,
+        Input::Line => {
+            write_all(sender, b"\r\n").await;
+            let line = line.take();
+            let mut out = Output::new();
+            match parse_line(&line) {
+                Ok(Some(command)) => run_command(sender, command, &mut out).await,
+                Ok(None) => {}
+                Err(e) => {
+                    writeln!(out, "Error: {}\r", e.message()).ok();
+                }
+            }
+            out.push_str(PROMPT).ok();
+            write_all(sender, out.as_bytes()).await;
+        }
+    }
+}
+
+async fn run_command(sender: &mut SerialSender, command: ShellCommand<'_>, out: &mut Output) {
+    match command {
+        ShellCommand::Help => {
+            out.push_str(HELP).ok();
+        }
+        ShellCommand::Status => {
+            let state = get_running_state().await;
+            writeln!(
+                out,
+                "{} {}\r",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            )
+            .ok();
+            writeln!(out, "Uptime: {}s\r", Instant::now().as_secs()).ok();
+            writeln!(
+                out,
+                "Server: {}\r",
+                match (state.server_connected, state.active) {
+                    (true, true) => "connected, active",
+                    (true, false) => "connected, inactive",
+                    _ => "disconnected",
+                }
+            )
+            .ok();
+            writeln!(out, "Keep awake: {}\r", on_off(state.keep_awake)).ok();
+            if let Some(progress) = state.typing_progress {
+                writeln!(out, "Typing: {progress}%\r").ok();
+            }
+            writeln!(
+                out,
+                "Sessions: {}, connect attempts: {}, HID timeouts: {}\r",
+                state.stats.sessions, state.stats.connect_attempts, state.stats.hid_report_timeouts
+            )
+            .ok();
+            writeln!(
+                out,
+                "Resets: {} ({} unexpected)\r",
+                state.stats.resets, state.stats.unexpected_resets
+            )
+            .ok();
+            if let Some(report) = get_reset_report() {
+                write!(out, "Last reset: {:?}, {:?}", report.reason, report.cause).ok();
+                if !report.location.is_empty() {
+                    write!(out, " at {}:{}", report.location, report.line).ok();
+                }
+                if !report.message.is_empty() {
+                    write!(out, ", {}", report.message).ok();
+                }
+                out.push_str("\r\n").ok();
+            }
+            writeln!(out, "Log level: {}\r", get_log_level()).ok();
+        }
+        ShellCommand::Net => {
+            let state = get_running_state().await;
+            match state.ip_address {
+                Some(ip) => writeln!(out, "IP address: {ip}\r"),
+                None => writeln!(out, "IP address: not connected\r"),
+            }
+            .ok();
+            match state.server_endpoint {
+                Some(endpoint) => writeln!(out, "Barrier server: {endpoint}\r"),
+                None => writeln!(out, "Barrier server: none\r"),
+            }
+            .ok();
+            let traffic = get_traffic();
+            writeln!(
+                out,
+                "Traffic: {} bytes in, {} bytes out\r",
+                traffic.bytes_in, traffic.bytes_out
+            )
+            .ok();
+        }
+        ShellCommand::ConfigGet(None) => {
+            // The whole config doesn't fit in the output buffer, the password is not included
+            let config = ConfigStore::current();
+            write_all(sender, &config.data[..config.len()]).await;
+            out.push_str("\r\n").ok();
+        }
+        ShellCommand::ConfigGet(Some(name)) => match AppConfig::get().get_field(name) {
+            Some(value) => {
+                writeln!(out, "{value}\r").ok();
+            }
+            None => {
+                writeln!(out, "Error: unknown setting '{name}'\r").ok();
+            }
+        },
+        ShellCommand::ConfigSet { name, value } => {
+            let mut config = AppConfig::get().clone();
+            let result = match config.set_field(name, value) {
+                Ok(_) => match ConfigStore::with_password(&config) {
+                    Ok(mut store) => match store.validate() {
+                        Ok(_) => store.commit().await,
+                        Err(e) => Err(e),
+                    },
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => writeln!(out, "Saved, reboot to apply\r"),
+                Err(e) => {
+                    warn!("Failed to set '{name}': {e:?}");
+                    writeln!(out, "Error: {}\r", ShellError::InvalidArgument.message())
+                }
+            }
+            .ok();
+        }
+        ShellCommand::Reconnect => {
+            request_reconnect();
+            writeln!(out, "Reconnecting\r").ok();
+        }
+        ShellCommand::KeepAwake(Some(keep_awake)) => {
+            set_keep_awake(keep_awake).await;
+            writeln!(out, "Keep awake: {}\r", on_off(keep_awake)).ok();
+        }
+        ShellCommand::KeepAwake(None) => {
+            let keep_awake = get_running_state().await.keep_awake;
+            writeln!(out, "Keep awake: {}\r", on_off(keep_awake)).ok();
+        }
+        ShellCommand::LogLevel(Some(level)) => {
+            let level = set_log_level(level);
+            writeln!(out, "Log level: {level}\r").ok();
+        }
+        ShellCommand::LogLevel(None) => {
+            writeln!(out, "Log level: {}\r", get_log_level()).ok();
+        }
+        ShellCommand::Reboot => {
+            write_all(sender, b"Rebooting\r\n").await;
+            reboot().await
+        }
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+#[cfg(feature = "wifi")]
+async fn handle_improv_command(sender: &mut SerialSender, data: &[u8]) -> Result<(), ImprovError> {
+    let command = parse_command(data)?;
+    log::debug!("Improv command: {command:?}");
+    match command {
+        Command::WifiSettings { ssid, password } => {
+            let mut config = AppConfig::get().clone();
+            let mut store = config
+                .set_field("ssid", ssid)
+                .and_then(|_| config.set_field("password", password))
+                .and_then(|_| ConfigStore::with_password(&config))
+                .and_then(|store| store.validate().map(|_| store))
+                .map_err(|e| {
+                    warn!("Invalid WiFi settings: {e:?}");
+                    ImprovError::InvalidRpc
+                })?;
+            send_improv_state(sender, ImprovState::Provisioning).await;
+            let settings = WifiSettings {
+                ssid: config.ssid.clone(),
+                password: config.password.0.clone(),
+            };
+            if !try_wifi_settings(settings).await {
+                send_improv_state(sender, ImprovState::Ready).await;
+                return Err(ImprovError::UnableToConnect);
+            }
+            store.commit().await.map_err(|e| {
+                warn!("Failed to save WiFi settings: {e:?}");
+                ImprovError::Unknown
+            })?;
+            // Joined the network, the board connects with the saved settings after reboot
+            send_improv_state(sender, ImprovState::Provisioned).await;
+            send_improv_result(sender, command.id(), &redirect_url()).await;
+            Timer::after(Duration::from_millis(500)).await;
+            info!("WiFi settings saved, rebooting...");
+            esp_hal::system::software_reset()
+        }
+        Command::GetCurrentState => {
+            let state = current_state().await;
+            send_improv_state(sender, state).await;
+            if state == ImprovState::Provisioned {
+                send_improv_result(sender, command.id(), &redirect_url()).await;
+            }
+        }
+        Command::GetDeviceInfo => {
+            send_improv_result(
+                sender,
+                command.id(),
+                &[
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION"),
+                    "ESP32-S3",
+                    AppConfig::get().screen_name.as_str(),
+                ],
+            )
+            .await;
+            // Already flashing if it fails
+            embassy_executor::Spawner::for_current_executor()
+                .await
+                .spawn(identify_task())
+                .ok();
+        }
+        // Scanning would disconnect the WiFi, report no networks
+        Command::GetWifiNetworks => send_improv_result(sender, command.id(), &[]).await,
+    }
+    Ok(())
+}
+
+#[cfg(feature = "wifi")]
+async fn current_state() -> ImprovState {
+    if AppConfig::is_loaded() || get_running_state().await.ip_address.is_some() {
+        ImprovState::Provisioned
+    } else {
+        ImprovState::Ready
+    }
+}
+
+#[cfg(feature = "wifi")]
+fn redirect_url() -> heapless::Vec<&'static str, 1> {
+    AppConfig::get()
+        .webusb_url
+        .iter()
+        .map(|url| url.as_str())
+        .collect()
+}
+
+/// Show the identify pattern on the indicator for a while so the user can find the board
+#[cfg(feature = "wifi")]
+#[embassy_executor::task]
+async fn identify_task() {
+    set_indicator_status(IndicatorStatus::Identify).await;
+    Timer::after(IDENTIFY_DURATION).await;
+    if get_ap_ssid().is_some() {
+        set_indicator_status(IndicatorStatus::Provisioning).await;
+    } else {
+        restore_indicator_status().await;
+    }
+}
+
+#[cfg(feature = "wifi")]
+async fn send_improv_state(sender: &mut SerialSender, state: ImprovState) {
+    write_all(sender, &encode(PacketType::CurrentState, &[state as u8])).await;
+}
+
+#[cfg(feature = "wifi")]
+async fn send_improv_error(sender: &mut SerialSender, error: ImprovError) {
+    write_all(sender, &encode(PacketType::ErrorState, &[error as u8])).await;
+}
+
+#[cfg(feature = "wifi")]
+async fn send_improv_result(sender: &mut SerialSender, command: u8, strings: &[&str]) {
+    let data = rpc_result(command, strings);
+    write_all(sender, &encode(PacketType::RpcResult, &data)).await;
+}
+
+async fn write_all(sender: &mut SerialSender, data: &[u8]) {
+    for chunk in data.chunks(MAX_PACKET_SIZE) {
+        if sender.write_packet(chunk).await.is_err() {
+            warn!("Failed to write to the serial port");
+            return;
+        }
+    }
+    if !data.is_empty() && data.len() % MAX_PACKET_SIZE == 0 {
+        // A full packet needs a zero-length packet to end the transfer
+        sender.write_packet(&[]).await.ok();
+    }
+}
@@ -0,0 +1,241 @@
+//! Line-oriented console on the USB serial port, the line editing and the command parser.
+
+use core::str::FromStr;
+
+use log::LevelFilter;
+
+const MAX_LINE_LENGTH: usize = 200;
+
+pub const HELP: &str = "\
+status                  Firmware and connection status\r
+net                     Network address and Barrier server\r
+config get [NAME]       Show the config, or one setting\r
+config set NAME VALUE   Change a setting, applied after reboot\r
+reconnect               Reconnect to the Barrier server\r
+keepawake [on|off]      Show or change keep awake\r
+log level [LEVEL]       Show or change the log level\r
+reboot                  Reboot the board\r
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShellCommand<'a> {
+    Help,
+    Status,
+    Net,
+    ConfigGet(Option<&'a str>),
+    ConfigSet { name: &'a str, value: &'a str },
+    Reconnect,
+    KeepAwake(Option<bool>),
+    LogLevel(Option<LevelFilter>),
+    Reboot,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShellError {
+    UnknownCommand,
+    MissingArgument,
+    InvalidArgument,
+}
+
+impl ShellError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            ShellError::UnknownCommand => "unknown command, try 'help'",
+            ShellError::MissingArgument => "missing argument",
+            ShellError::InvalidArgument => "invalid argument",
+        }
+    }
+}
+
+/// Parse a command line, returns `Ok(None)` for an empty line.
+///
+/// The value of `config set` is the rest of the line, so it can contain spaces.
+pub fn parse_line(line: &str) -> Result<Option<ShellCommand<'_>>, ShellError> {
+    let line = line.trim();
+    let (command, rest) = split_word(line);
+    let (arg, tail) = split_word(rest);
+    let command = match command {
+        "" => return Ok(None),
+        "help" | "?" => ShellCommand::Help,
+        "status" => ShellCommand::Status,
+        "net" => ShellCommand::Net,
+        "config" => match arg {
+            "get" => ShellCommand::ConfigGet(Some(tail).filter(|s| !s.is_empty())),
+            "set" => {
+                let (name, value) = split_word(tail);
+                if name.is_empty() {
+                    return Err(ShellError::MissingArgument);
+                }
+                ShellCommand::ConfigSet { name, value }
+            }
+            "" => return Err(ShellError::MissingArgument),
+            _ => return Err(ShellError::InvalidArgument),
+        },
+        "reconnect" => ShellCommand::Reconnect,
+        "keepawake" => ShellCommand::KeepAwake(match arg {
+            "" => None,
+            "on" | "1" | "true" => Some(true),
+            "off" | "0" | "false" => Some(false),
+            _ => return Err(ShellError::InvalidArgument),
+        }),
+        "log" => match arg {
+            "level" if tail.is_empty() => ShellCommand::LogLevel(None),
+            "level" => ShellCommand::LogLevel(Some(
+                LevelFilter::from_str(tail).map_err(|_| ShellError::InvalidArgument)?,
+            )),
+            "" => return Err(ShellError::MissingArgument),
+            _ => return Err(ShellError::InvalidArgument),
+        },
+        "reboot" => ShellCommand::Reboot,
+        _ => return Err(ShellError::UnknownCommand),
+    };
+    Ok(Some(command))
+}
+
+fn split_word(s: &str) -> (&str, &str) {
+    match s.split_once(' ') {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (s, ""),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    // Nothing to show
+    None,
+    // Echo the byte back
+    Echo(u8),
+    // Erase the last character on the terminal
+    Erase,
+    // The line is complete, take it with `LineBuffer::take`
+    Line,
+}
+
+/// Collects the typed characters until Enter is pressed
+#[derive(Default)]
+pub struct LineBuffer {
+    line: heapless::String<MAX_LINE_LENGTH>,
+    // The previous byte was CR, so LF of CRLF is skipped
+    cr: bool,
+}
+
+impl LineBuffer {
+    pub fn push(&mut self, byte: u8) -> Input {
+        let cr = core::mem::replace(&mut self.cr, byte == b'\r');
+        match byte {
+            b'\n' if cr => Input::None,
+            b'\r' | b'\n' => Input::Line,
+            // Backspace and DEL
+            0x08 | 0x7F => match self.line.pop() {
+                Some(_) => Input::Erase,
+                None => Input::None,
+            },
+            // Printable ASCII only, longer lines are cut
+            0x20..0x7F if self.line.push(byte as char).is_ok() => Input::Echo(byte),
+            _ => Input::None,
+        }
+    }
+
+    pub fn take(&mut self) -> heapless::String<MAX_LINE_LENGTH> {
+        core::mem::take(&mut self.line)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use log::LevelFilter;
+
+    use super::{Input, LineBuffer, ShellCommand, ShellError, parse_line};
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line(""), Ok(None));
+        assert_eq!(parse_line("   "), Ok(None));
+        assert_eq!(parse_line("status"), Ok(Some(ShellCommand::Status)));
+        assert_eq!(parse_line(" net "), Ok(Some(ShellCommand::Net)));
+        assert_eq!(parse_line("reconnect"), Ok(Some(ShellCommand::Reconnect)));
+        assert_eq!(parse_line("reboot"), Ok(Some(ShellCommand::Reboot)));
+        assert_eq!(parse_line("?"), Ok(Some(ShellCommand::Help)));
+        assert_eq!(parse_line("foo"), Err(ShellError::UnknownCommand));
+    }
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(
+            parse_line("config get"),
+            Ok(Some(ShellCommand::ConfigGet(None)))
+        );
+        assert_eq!(
+            parse_line("config get screen_width"),
+            Ok(Some(ShellCommand::ConfigGet(Some("screen_width"))))
+        );
+        assert_eq!(
+            parse_line("config  set ssid My Home WiFi"),
+            Ok(Some(ShellCommand::ConfigSet {
+                name: "ssid",
+                value: "My Home WiFi"
+            }))
+        );
+        assert_eq!(
+            parse_line("config set password"),
+            Ok(Some(ShellCommand::ConfigSet {
+                name: "password",
+                value: ""
+            }))
+        );
+        assert_eq!(parse_line("config set"), Err(ShellError::MissingArgument));
+        assert_eq!(parse_line("config"), Err(ShellError::MissingArgument));
+        assert_eq!(parse_line("config del x"), Err(ShellError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(
+            parse_line("keepawake"),
+            Ok(Some(ShellCommand::KeepAwake(None)))
+        );
+        assert_eq!(
+            parse_line("keepawake on"),
+            Ok(Some(ShellCommand::KeepAwake(Some(true))))
+        );
+        assert_eq!(
+            parse_line("keepawake 0"),
+            Ok(Some(ShellCommand::KeepAwake(Some(false))))
+        );
+        assert_eq!(
+            parse_line("keepawake maybe"),
+            Err(ShellError::InvalidArgument)
+        );
+        assert_eq!(
+            parse_line("log level"),
+            Ok(Some(ShellCommand::LogLevel(None)))
+        );
+        assert_eq!(
+            parse_line("log level DEBUG"),
+            Ok(Some(ShellCommand::LogLevel(Some(LevelFilter::Debug))))
+        );
+        assert_eq!(
+            parse_line("log level loud"),
+            Err(ShellError::InvalidArgument)
+        );
+        assert_eq!(parse_line("log"), Err(ShellError::MissingArgument));
+    }
+
+    #[test]
+    fn test_line_buffer() {
+        let mut buffer = LineBuffer::default();
+        assert_eq!(buffer.push(b'n'), Input::Echo(b'n'));
+        assert_eq!(buffer.push(b'x'), Input::Echo(b'x'));
+        assert_eq!(buffer.push(0x7F), Input::Erase);
+        assert_eq!(buffer.push(b'e'), Input::Echo(b'e'));
+        assert_eq!(buffer.push(0x1B), Input::None);
+        assert_eq!(buffer.push(b't'), Input::Echo(b't'));
+        assert_eq!(buffer.push(b'\r'), Input::Line);
+        assert_eq!(buffer.take().as_str(), "net");
+        // LF of CRLF doesn't make another line
+        assert_eq!(buffer.push(b'\n'), Input::None);
+        assert_eq!(buffer.push(0x08), Input::None);
+        assert_eq!(buffer.push(b'\n'), Input::Line);
+        assert_eq!(buffer.take().as_str(), "");
+    }
+}
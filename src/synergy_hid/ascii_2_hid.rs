@@ -104,3 +104,85 @@ pub const ASCII_2_HID: [[u8; 2]; 128] = [
     [HID_KEY_GRAVE, HID_KEY_SHIFT_LEFT],        // 126 '~'
     [0, 0],                                     // 127
 ];
+
+/// Keyboard layout of the host, used to type ASCII text
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum TypingLayout {
+    #[default]
+    Us = 0,
+    Uk = 1,
+    De = 2,
+}
+
+#[rustfmt::skip]
+const UK_OVERRIDES: [(u8, [u8; 2]); 6] = [
+    (b'"', [HID_KEY_2, HID_KEY_SHIFT_LEFT]),
+    (b'#', [HID_KEY_EUROPE_1, 0]),
+    (b'@', [HID_KEY_APOSTROPHE, HID_KEY_SHIFT_LEFT]),
+    (b'\\', [HID_KEY_EUROPE_2, 0]),
+    (b'|', [HID_KEY_EUROPE_2, HID_KEY_SHIFT_LEFT]),
+    (b'~', [HID_KEY_EUROPE_1, HID_KEY_SHIFT_LEFT]),
+];
+
+// '^' and '`' are dead keys on the German layout, they are not typed
+#[rustfmt::skip]
+const DE_OVERRIDES: [(u8, [u8; 2]); 31] = [
+    (b'"', [HID_KEY_2, HID_KEY_SHIFT_LEFT]),
+    (b'#', [HID_KEY_EUROPE_1, 0]),
+    (b'&', [HID_KEY_6, HID_KEY_SHIFT_LEFT]),
+    (b'\'', [HID_KEY_EUROPE_1, HID_KEY_SHIFT_LEFT]),
+    (b'(', [HID_KEY_8, HID_KEY_SHIFT_LEFT]),
+    (b')', [HID_KEY_9, HID_KEY_SHIFT_LEFT]),
+    (b'*', [HID_KEY_BRACKET_RIGHT, HID_KEY_SHIFT_LEFT]),
+    (b'+', [HID_KEY_BRACKET_RIGHT, 0]),
+    (b'-', [HID_KEY_SLASH, 0]),
+    (b'/', [HID_KEY_7, HID_KEY_SHIFT_LEFT]),
+    (b':', [HID_KEY_PERIOD, HID_KEY_SHIFT_LEFT]),
+    (b';', [HID_KEY_COMMA, HID_KEY_SHIFT_LEFT]),
+    (b'<', [HID_KEY_EUROPE_2, 0]),
+    (b'=', [HID_KEY_0, HID_KEY_SHIFT_LEFT]),
+    (b'>', [HID_KEY_EUROPE_2, HID_KEY_SHIFT_LEFT]),
+    (b'?', [HID_KEY_MINUS, HID_KEY_SHIFT_LEFT]),
+    (b'@', [HID_KEY_Q, HID_KEY_ALT_RIGHT]),
+    (b'Y', [HID_KEY_Z, HID_KEY_SHIFT_LEFT]),
+    (b'Z', [HID_KEY_Y, HID_KEY_SHIFT_LEFT]),
+    (b'[', [HID_KEY_8, HID_KEY_ALT_RIGHT]),
+    (b'\\', [HID_KEY_MINUS, HID_KEY_ALT_RIGHT]),
+    (b']', [HID_KEY_9, HID_KEY_ALT_RIGHT]),
+    (b'^', [0, 0]),
+    (b'_', [HID_KEY_SLASH, HID_KEY_SHIFT_LEFT]),
+    (b'`', [0, 0]),
+    (b'y', [HID_KEY_Z, 0]),
+    (b'z', [HID_KEY_Y, 0]),
+    (b'{', [HID_KEY_7, HID_KEY_ALT_RIGHT]),
+    (b'|', [HID_KEY_EUROPE_2, HID_KEY_ALT_RIGHT]),
+    (b'}', [HID_KEY_0, HID_KEY_ALT_RIGHT]),
+    (b'~', [HID_KEY_BRACKET_RIGHT, HID_KEY_ALT_RIGHT]),
+];
+
+impl TypingLayout {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Us),
+            1 => Some(Self::Uk),
+            2 => Some(Self::De),
+            _ => None,
+        }
+    }
+
+    /// HID key code and modifier to type the ASCII character, `[0, 0]` if it cannot be typed.
+    pub fn ascii_to_hid(self, c: u8) -> [u8; 2] {
+        let overrides: &[(u8, [u8; 2])] = match self {
+            Self::Us => &[],
+            Self::Uk => &UK_OVERRIDES,
+            Self::De => &DE_OVERRIDES,
+        };
+        overrides
+            .iter()
+            .find(|(ch, _)| *ch == c)
+            .map(|(_, key)| *key)
+            .unwrap_or_else(|| ASCII_2_HID.get(c as usize).copied().unwrap_or([0, 0]))
+    }
+}
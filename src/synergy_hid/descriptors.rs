@@ -41,15 +41,12 @@ pub const COMPOSITE_REPORT_DESCRIPTOR: &[u8] = &[
     0xA1, 0x00,        //   Collection (Physical)
     0x05, 0x09,        //     Usage Page (Button)
     0x19, 0x01,        //     Usage Minimum (0x01)
-    0x29, 0x05,        //     Usage Maximum (0x05)
+    0x29, 0x08,        //     Usage Maximum (0x08)
     0x15, 0x00,        //     Logical Minimum (0)
     0x25, 0x01,        //     Logical Maximum (1)
-    0x95, 0x05,        //     Report Count (5)
+    0x95, 0x08,        //     Report Count (8)
     0x75, 0x01,        //     Report Size (1)
     0x81, 0x02,        //     Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
-    0x95, 0x01,        //     Report Count (1)
-    0x75, 0x03,        //     Report Size (3)
-    0x81, 0x01,        //     Input (Const,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
     0x05, 0x01,        //     Usage Page (Generic Desktop Controls)
     0x09, 0x30,        //     Usage (X)
     0x09, 0x31,        //     Usage (Y)
@@ -86,3 +83,48 @@ pub const COMPOSITE_REPORT_DESCRIPTOR: &[u8] = &[
     0x81, 0x00,        //   Input (Data,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
     0xC0,              // End Collection
 ];
+
+// Apple vendor top case page, only understood by macOS, where the Fn/Globe key lives.
+// Wrapped in a keyboard application collection so that macOS merges it with report 1.
+#[rustfmt::skip]
+pub const APPLE_VENDOR_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,        // Usage Page (Generic Desktop Ctrls)
+    0x09, 0x06,        // Usage (Keyboard)
+    0xA1, 0x01,        // Collection (Application)
+    0x85, 0x04,        //   Report ID (4)
+    0x06, 0xFF, 0x00,  //   Usage Page (AppleVendor Top Case 0x00FF)
+    0x09, 0x03,        //   Usage (Keyboard Fn)
+    0x15, 0x00,        //   Logical Minimum (0)
+    0x25, 0x01,        //   Logical Maximum (1)
+    0x95, 0x01,        //   Report Count (1)
+    0x75, 0x01,        //   Report Size (1)
+    0x81, 0x02,        //   Input (Data,Var,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0x95, 0x01,        //   Report Count (1)
+    0x75, 0x07,        //   Report Size (7)
+    0x81, 0x01,        //   Input (Const,Array,Abs,No Wrap,Linear,Preferred State,No Null Position)
+    0xC0,              // End Collection
+];
+
+const COMPOSITE_WITH_APPLE_VENDOR_LEN: usize =
+    COMPOSITE_REPORT_DESCRIPTOR.len() + APPLE_VENDOR_REPORT_DESCRIPTOR.len();
+
+pub const COMPOSITE_REPORT_DESCRIPTOR_WITH_APPLE_VENDOR: &[u8] =
+    &concat::<COMPOSITE_WITH_APPLE_VENDOR_LEN>(
+        COMPOSITE_REPORT_DESCRIPTOR,
+        APPLE_VENDOR_REPORT_DESCRIPTOR,
+    );
+
+const fn concat<const N: usize>(a: &[u8], b: &[u8]) -> [u8; N] {
+    let mut ret = [0u8; N];
+    let mut i = 0;
+    while i < a.len() {
+        ret[i] = a[i];
+        i += 1;
+    }
+    let mut j = 0;
+    while j < b.len() {
+        ret[i + j] = b[j];
+        j += 1;
+    }
+    ret
+}
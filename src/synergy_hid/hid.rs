@@ -176,3 +176,33 @@ impl ConsumerReport {
         report
     }
 }
+
+#[derive(Debug, Default)]
+pub struct AppleVendorReport {
+    top_case: u8,
+}
+
+impl AppleVendorReport {
+    pub fn press(&mut self, usage: u8) -> [u8; 1] {
+        self.top_case |= usage;
+        self.send()
+    }
+
+    pub fn release(&mut self, usage: u8) -> [u8; 1] {
+        self.top_case &= !usage;
+        self.send()
+    }
+
+    pub fn clear(&mut self) -> [u8; 1] {
+        self.top_case = 0;
+        self.send()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.top_case == 0
+    }
+
+    fn send(&self) -> [u8; 1] {
+        [self.top_case]
+    }
+}
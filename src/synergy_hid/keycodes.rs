@@ -242,8 +242,12 @@ pub enum KeyCode {
     None,
     Key(u8),
     Consumer(u16),
+    AppleVendor(u8),
 }
 
+// Bit of the Keyboard Fn usage (0x00FF/0x03) in the Apple vendor report
+pub const APPLE_VENDOR_KEYBOARD_FN: u8 = 0x01;
+
 pub fn synergy_to_hid(id: u16) -> KeyCode {
     if id == 0xEE20 {
         // HACK: Synergy sends kKeyLeftTab(0xEE20) when the pressing GUI+SHIFT+TAB, but kKeyTab when pressing GUI+TAB.
@@ -276,6 +280,7 @@ pub fn synergy_to_hid(id: u16) -> KeyCode {
 // MOUSE_BUTTON_MIDDLE   = TU_BIT(2), ///< Middle button
 // MOUSE_BUTTON_BACKWARD = TU_BIT(3), ///< Backward button,
 // MOUSE_BUTTON_FORWARD  = TU_BIT(4), ///< Forward button,
+// Button 6-8 are TU_BIT(5)-TU_BIT(7), they have no standard meaning.
 
 pub fn synergy_mouse_button(button: i8) -> u8 {
     match button {
@@ -289,6 +294,18 @@ pub fn synergy_mouse_button(button: i8) -> u8 {
         4 => 0x08,
         // MOUSE_BUTTON_FORWARD
         5 => 0x10,
+        // Extra buttons on gaming mice
+        6 => 0x20,
+        7 => 0x40,
+        8 => 0x80,
+        _ => 0,
+    }
+}
+
+/// Convert a HID button number (1-8) to the bit in the mouse report, 0 disables the button.
+pub fn hid_mouse_button(button: u8) -> u8 {
+    match button {
+        1..=8 => 1 << (button - 1),
         _ => 0,
     }
 }
@@ -1,16 +1,16 @@
-#[cfg(feature = "clipboard")]
 mod ascii_2_hid;
 mod descriptors;
 mod hid;
 mod keycodes;
 
-#[cfg(feature = "clipboard")]
-pub(super) use ascii_2_hid::ASCII_2_HID;
-use descriptors::COMPOSITE_REPORT_DESCRIPTOR;
+pub use ascii_2_hid::TypingLayout;
+use descriptors::{COMPOSITE_REPORT_DESCRIPTOR, COMPOSITE_REPORT_DESCRIPTOR_WITH_APPLE_VENDOR};
 pub(super) use hid::KeyboardReport;
 pub(super) use hid::*;
 pub use keycodes::modifier_mask_to_synergy;
-pub(crate) use keycodes::{KeyCode, synergy_mouse_button, synergy_to_hid};
+pub(crate) use keycodes::{
+    APPLE_VENDOR_KEYBOARD_FN, KeyCode, hid_mouse_button, synergy_mouse_button, synergy_to_hid,
+};
 
 use log::{debug, warn};
 
@@ -20,6 +20,7 @@ pub enum ReportType {
     Keyboard = 1,
     Mouse = 2,
     Consumer = 3,
+    AppleVendor = 4,
 }
 
 impl ReportType {
@@ -28,6 +29,7 @@ impl ReportType {
             Self::Keyboard => 9,
             Self::Mouse => 8,
             Self::Consumer => 3,
+            Self::AppleVendor => 2,
         }
     }
     pub const fn get_max_report_size() -> usize {
@@ -38,6 +40,10 @@ impl ReportType {
 #[derive(Debug)]
 pub struct SynergyHid {
     flip_mouse_wheel: bool,
+    // Server key mapped to the Apple Fn/Globe key, `None` if the Apple vendor report is disabled
+    apple_fn_key: Option<u16>,
+    // Report bit of each server mouse button, indexed by the button id - 1
+    mouse_buttons: [u8; 8],
     server_buttons: [u16; 512],
 
     // Report 1
@@ -46,26 +52,66 @@ pub struct SynergyHid {
     mouse_report: AbsMouseReport,
     // Report 3
     consumer_report: ConsumerReport,
+    // Report 4
+    apple_vendor_report: AppleVendorReport,
 }
 
 impl SynergyHid {
     pub fn new(flip_mouse_wheel: bool) -> Self {
         Self {
             flip_mouse_wheel,
+            apple_fn_key: None,
+            mouse_buttons: core::array::from_fn(|i| synergy_mouse_button(i as i8 + 1)),
             server_buttons: [0; 512],
             keyboard_report: KeyboardReport::default(),
             mouse_report: AbsMouseReport::default(),
             consumer_report: ConsumerReport::default(),
+            apple_vendor_report: AppleVendorReport::default(),
         }
     }
 
-    pub const fn get_report_descriptor() -> (u8, &'static [u8]) {
+    /// Map the server key `key` to the Apple Fn/Globe key, the report descriptor must be
+    /// created with `apple_vendor` set, otherwise the host will ignore the report.
+    pub fn with_apple_fn_key(mut self, key: Option<u16>) -> Self {
+        self.apple_fn_key = key;
+        self
+    }
+
+    /// Remap server mouse buttons, `map[n]` is the HID button number (1-8) sent for the server
+    /// button `n + 1`, 0 disables the button. Buttons not covered by `map` keep the default.
+    pub fn with_mouse_button_map(mut self, map: &[u8]) -> Self {
+        for (bit, button) in self.mouse_buttons.iter_mut().zip(map) {
+            *bit = hid_mouse_button(*button);
+        }
+        self
+    }
+
+    pub const fn get_report_descriptor(apple_vendor: bool) -> (u8, &'static [u8]) {
         (
             ReportType::get_max_report_size() as u8,
-            COMPOSITE_REPORT_DESCRIPTOR,
+            if apple_vendor {
+                COMPOSITE_REPORT_DESCRIPTOR_WITH_APPLE_VENDOR
+            } else {
+                COMPOSITE_REPORT_DESCRIPTOR
+            },
         )
     }
 
+    fn map_mouse_button(&self, button: i8) -> u8 {
+        match button {
+            1..=8 => self.mouse_buttons[button as usize - 1],
+            _ => 0,
+        }
+    }
+
+    fn map_key(&self, key: u16) -> KeyCode {
+        if key != 0 && self.apple_fn_key == Some(key) {
+            KeyCode::AppleVendor(APPLE_VENDOR_KEYBOARD_FN)
+        } else {
+            synergy_to_hid(key)
+        }
+    }
+
     pub fn key_down<'a>(
         &mut self,
         key: u16,
@@ -75,28 +121,12 @@ impl SynergyHid {
     ) -> (ReportType, &'a [u8]) {
         debug!("Key down {key} {mask} {button}");
         self.server_buttons[button as usize] = key;
-        let hid = synergy_to_hid(key);
+        let hid = self.map_key(key);
         // debug!("Key Down {:#04x} -> Keycode: {:?}", key, hid);
-        match hid {
-            KeyCode::None => {
-                if key != 0 {
-                    warn!("Keycode {key} not found");
-                }
-                report[0] = ReportType::Keyboard as u8;
-                report[1..9].copy_from_slice(&self.keyboard_report.clear());
-                (ReportType::Keyboard, &report[0..9])
-            }
-            KeyCode::Key(key) => {
-                report[0] = ReportType::Keyboard as u8;
-                report[1..9].copy_from_slice(&self.keyboard_report.press(key));
-                (ReportType::Keyboard, &report[0..9])
-            }
-            KeyCode::Consumer(key) => {
-                report[0] = ReportType::Consumer as u8;
-                report[1..3].copy_from_slice(&self.consumer_report.press(key));
-                (ReportType::Consumer, &report[0..3])
-            }
+        if matches!(hid, KeyCode::None) && key != 0 {
+            warn!("Keycode {key} not found");
         }
+        self.press_key_code(hid, report)
     }
 
     pub fn key_up<'a>(
@@ -111,7 +141,7 @@ impl SynergyHid {
         let hid = if self.server_buttons[button as usize] != 0 {
             // debug!("Key {key} up");
             self.server_buttons[button as usize] = 0;
-            synergy_to_hid(key)
+            self.map_key(key)
         } else if key == 0 {
             debug!("Key 0 up, clear all key down");
             KeyCode::None
@@ -120,15 +150,72 @@ impl SynergyHid {
             KeyCode::None
         };
         // debug!("Key Down {:#04x} -> Keycode: {:?}", key, hid);
-        match hid {
+        if matches!(hid, KeyCode::None) && key != 0 {
+            warn!("Keycode {key} not found");
+        }
+        self.release_key_code(hid, report)
+    }
+
+    /// Press a key on behalf of the firmware, e.g. a configured chord.
+    ///
+    /// Unlike `key_down` no server button is taken, so the keys the server holds stay down.
+    /// Returns `None` if the key has no HID mapping, there is nothing to send.
+    pub fn chord_key_down<'a>(
+        &mut self,
+        key: u16,
+        report: &'a mut [u8],
+    ) -> Option<(ReportType, &'a [u8])> {
+        match self.map_key(key) {
             KeyCode::None => {
-                if key != 0 {
-                    warn!("Keycode {key} not found");
-                }
+                warn!("Keycode {key} not found");
+                None
+            }
+            hid => Some(self.press_key_code(hid, report)),
+        }
+    }
+
+    /// Release a key pressed with `chord_key_down`.
+    pub fn chord_key_up<'a>(
+        &mut self,
+        key: u16,
+        report: &'a mut [u8],
+    ) -> Option<(ReportType, &'a [u8])> {
+        match self.map_key(key) {
+            KeyCode::None => None,
+            hid => Some(self.release_key_code(hid, report)),
+        }
+    }
+
+    /// The report with the key pressed, an unmapped key releases all keyboard keys.
+    fn press_key_code<'a>(&mut self, hid: KeyCode, report: &'a mut [u8]) -> (ReportType, &'a [u8]) {
+        match hid {
+            KeyCode::None => self.clear_keyboard(report),
+            KeyCode::Key(key) => {
                 report[0] = ReportType::Keyboard as u8;
-                report[1..9].copy_from_slice(&self.keyboard_report.clear());
+                report[1..9].copy_from_slice(&self.keyboard_report.press(key));
                 (ReportType::Keyboard, &report[0..9])
             }
+            KeyCode::Consumer(key) => {
+                report[0] = ReportType::Consumer as u8;
+                report[1..3].copy_from_slice(&self.consumer_report.press(key));
+                (ReportType::Consumer, &report[0..3])
+            }
+            KeyCode::AppleVendor(usage) => {
+                report[0] = ReportType::AppleVendor as u8;
+                report[1..2].copy_from_slice(&self.apple_vendor_report.press(usage));
+                (ReportType::AppleVendor, &report[0..2])
+            }
+        }
+    }
+
+    /// The report with the key released, an unmapped key releases all keyboard keys.
+    fn release_key_code<'a>(
+        &mut self,
+        hid: KeyCode,
+        report: &'a mut [u8],
+    ) -> (ReportType, &'a [u8]) {
+        match hid {
+            KeyCode::None => self.clear_keyboard(report),
             KeyCode::Key(key) => {
                 report[0] = ReportType::Keyboard as u8;
                 report[1..9].copy_from_slice(&self.keyboard_report.release(key));
@@ -139,9 +226,20 @@ impl SynergyHid {
                 report[1..3].copy_from_slice(&self.consumer_report.release());
                 (ReportType::Consumer, &report[0..3])
             }
+            KeyCode::AppleVendor(usage) => {
+                report[0] = ReportType::AppleVendor as u8;
+                report[1..2].copy_from_slice(&self.apple_vendor_report.release(usage));
+                (ReportType::AppleVendor, &report[0..2])
+            }
         }
     }
 
+    fn clear_keyboard<'a>(&mut self, report: &'a mut [u8]) -> (ReportType, &'a [u8]) {
+        report[0] = ReportType::Keyboard as u8;
+        report[1..9].copy_from_slice(&self.keyboard_report.clear());
+        (ReportType::Keyboard, &report[0..9])
+    }
+
     pub fn set_cursor_position<'a>(
         &mut self,
         x: u16,
@@ -155,13 +253,13 @@ impl SynergyHid {
 
     pub fn mouse_down<'a>(&mut self, button: i8, report: &'a mut [u8]) -> (ReportType, &'a [u8]) {
         report[0] = ReportType::Mouse as u8;
-        report[1..8].copy_from_slice(&self.mouse_report.mouse_down(synergy_mouse_button(button)));
+        report[1..8].copy_from_slice(&self.mouse_report.mouse_down(self.map_mouse_button(button)));
         (ReportType::Mouse, &report[..8])
     }
 
     pub fn mouse_up<'a>(&mut self, button: i8, report: &'a mut [u8]) -> (ReportType, &'a [u8]) {
         report[0] = ReportType::Mouse as u8;
-        report[1..8].copy_from_slice(&self.mouse_report.mouse_up(synergy_mouse_button(button)));
+        report[1..8].copy_from_slice(&self.mouse_report.mouse_up(self.map_mouse_button(button)));
         (ReportType::Mouse, &report[..8])
     }
 
@@ -189,12 +287,9 @@ impl SynergyHid {
         report_type: ReportType,
         report: &'a mut [u8],
     ) -> (ReportType, &'a [u8]) {
+        self.forget_server_buttons(report_type);
         match report_type {
-            ReportType::Keyboard => {
-                report[0] = ReportType::Keyboard as u8;
-                report[1..9].copy_from_slice(&self.keyboard_report.clear());
-                (ReportType::Keyboard, &report[0..9])
-            }
+            ReportType::Keyboard => self.clear_keyboard(report),
             ReportType::Mouse => {
                 report[0] = ReportType::Mouse as u8;
                 report[1..8].copy_from_slice(&self.mouse_report.clear());
@@ -205,6 +300,27 @@ impl SynergyHid {
                 report[1..3].copy_from_slice(&self.consumer_report.clear());
                 (ReportType::Consumer, &report[0..3])
             }
+            ReportType::AppleVendor => {
+                report[0] = ReportType::AppleVendor as u8;
+                report[1..2].copy_from_slice(&self.apple_vendor_report.clear());
+                (ReportType::AppleVendor, &report[0..2])
+            }
+        }
+    }
+
+    /// Forget the server buttons holding keys of the cleared report, so they don't release keys
+    /// pressed again later.
+    fn forget_server_buttons(&mut self, report_type: ReportType) {
+        for i in 0..self.server_buttons.len() {
+            let report = match self.map_key(self.server_buttons[i]) {
+                KeyCode::None => continue,
+                KeyCode::Key(_) => ReportType::Keyboard,
+                KeyCode::Consumer(_) => ReportType::Consumer,
+                KeyCode::AppleVendor(_) => ReportType::AppleVendor,
+            };
+            if report == report_type {
+                self.server_buttons[i] = 0;
+            }
         }
     }
 
@@ -212,14 +328,18 @@ impl SynergyHid {
         self.keyboard_report.is_empty()
             && self.mouse_report.is_empty()
             && self.consumer_report.is_empty()
+            && self.apple_vendor_report.is_empty()
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
-        ReportType,
-        keycodes::{HID_KEY_A, HID_KEY_B},
+        ReportType, TypingLayout,
+        keycodes::{
+            HID_KEY_A, HID_KEY_ALT_RIGHT, HID_KEY_B, HID_KEY_EUROPE_2, HID_KEY_Q,
+            HID_KEY_SHIFT_LEFT, HID_KEY_Y, HID_KEY_Z,
+        },
     };
 
     #[test]
@@ -264,4 +384,118 @@ mod test {
             (ReportType::Consumer, [0x00, 0xE2].as_ref())
         );
     }
+
+    #[test]
+    fn test_chord_keeps_held_keys() {
+        let mut hid = super::SynergyHid::new(false);
+        let mut report = [0; 9];
+        hid.key_down('A' as u16, 0x0000, 0x001E, &mut report);
+        // kKeyControl_L(0xEFE3) + 'B'
+        assert_eq!(
+            hid.chord_key_down(0xEFE3, &mut report),
+            Some((
+                ReportType::Keyboard,
+                [1, 0x01, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            ))
+        );
+        hid.chord_key_down('B' as u16, &mut report);
+        hid.chord_key_up('B' as u16, &mut report);
+        assert_eq!(
+            hid.chord_key_up(0xEFE3, &mut report),
+            Some((
+                ReportType::Keyboard,
+                [1, 0, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            ))
+        );
+        // Unknown keys are skipped
+        assert_eq!(hid.chord_key_down(0xFFFF, &mut report), None);
+        // The held key is still tracked and released by its server button
+        assert_eq!(
+            hid.key_up('A' as u16, 0x0000, 0x001E, &mut report),
+            (ReportType::Keyboard, [1, 0, 0, 0, 0, 0, 0, 0, 0].as_ref())
+        );
+        assert!(hid.is_empty());
+    }
+
+    #[test]
+    fn test_clear_forgets_server_buttons() {
+        let mut hid = super::SynergyHid::new(false);
+        let mut report = [0; 9];
+        hid.key_down('A' as u16, 0x0000, 0x001E, &mut report);
+        // kKeyAudioMute(0xE0AD)
+        hid.key_down(0xE0AD, 0x0000, 0x0071, &mut report);
+        hid.clear(ReportType::Keyboard, &mut report);
+        assert_eq!(hid.server_buttons[0x001E], 0);
+        // Other reports keep their keys
+        assert_eq!(
+            hid.key_up(0xE0AD, 0x0000, 0x0071, &mut report),
+            (ReportType::Consumer, [3, 0x00, 0x00].as_ref())
+        );
+        assert!(hid.is_empty());
+    }
+
+    #[test]
+    fn test_typing_layout() {
+        assert_eq!(TypingLayout::Us.ascii_to_hid(b'z'), [HID_KEY_Z, 0]);
+        assert_eq!(TypingLayout::De.ascii_to_hid(b'z'), [HID_KEY_Y, 0]);
+        assert_eq!(
+            TypingLayout::De.ascii_to_hid(b'@'),
+            [HID_KEY_Q, HID_KEY_ALT_RIGHT]
+        );
+        assert_eq!(
+            TypingLayout::Uk.ascii_to_hid(b'|'),
+            [HID_KEY_EUROPE_2, HID_KEY_SHIFT_LEFT]
+        );
+        // Same as US layout if not overridden
+        assert_eq!(
+            TypingLayout::De.ascii_to_hid(b'a'),
+            TypingLayout::Us.ascii_to_hid(b'a')
+        );
+        // Dead keys and non-ASCII characters cannot be typed
+        assert_eq!(TypingLayout::De.ascii_to_hid(b'^'), [0, 0]);
+        assert_eq!(TypingLayout::Us.ascii_to_hid(0xC3), [0, 0]);
+    }
+
+    #[test]
+    fn test_apple_fn_key() {
+        // kKeyCapsLock(0xEFE5) is mapped to the Apple Fn/Globe key
+        let mut hid = super::SynergyHid::new(false).with_apple_fn_key(Some(0xEFE5));
+        let mut report = [0; 9];
+        assert_eq!(
+            hid.key_down(0xEFE5, 0x0000, 1, &mut report),
+            (ReportType::AppleVendor, [4, 0x01].as_ref())
+        );
+        assert!(!hid.is_empty());
+        assert_eq!(
+            hid.key_down('A' as u16, 0x0000, 2, &mut report),
+            (
+                ReportType::Keyboard,
+                [1, 0, 0, HID_KEY_A, 0, 0, 0, 0, 0].as_ref()
+            )
+        );
+        assert_eq!(
+            hid.key_up(0xEFE5, 0x0000, 1, &mut report),
+            (ReportType::AppleVendor, [4, 0x00].as_ref())
+        );
+    }
+
+    #[test]
+    fn test_mouse_button_map() {
+        let mut hid = super::SynergyHid::new(false);
+        let mut report = [0; 9];
+        // Default mapping, server middle button is HID button 3
+        assert_eq!(hid.mouse_down(2, &mut report).1[1], 0x04);
+        assert_eq!(hid.mouse_up(2, &mut report).1[1], 0x00);
+        // Extra buttons
+        assert_eq!(hid.mouse_down(8, &mut report).1[1], 0x80);
+        assert_eq!(hid.mouse_up(8, &mut report).1[1], 0x00);
+
+        // Left-handed, swap left and right buttons, and disable button 6
+        let mut hid = super::SynergyHid::new(false).with_mouse_button_map(&[2, 3, 1, 4, 5, 0]);
+        assert_eq!(hid.mouse_down(1, &mut report).1[1], 0x02);
+        assert_eq!(hid.mouse_down(3, &mut report).1[1], 0x03);
+        assert_eq!(hid.mouse_up(1, &mut report).1[1], 0x01);
+        assert_eq!(hid.mouse_down(6, &mut report).1[1], 0x01);
+        assert_eq!(hid.mouse_down(7, &mut report).1[1], 0x41);
+    }
 }
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_KEY_DELAY: u16 = 5;
+const DEFAULT_MODIFIER_DELAY: u16 = 5;
+
+/// How the clipboard is typed into the host, all delays are in milliseconds.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TypingConfig {
+    // Delay after each key press and release
+    #[serde(default = "get_default_key_delay")]
+    pub key_delay: u16,
+    // Delay after each modifier press and release
+    #[serde(default = "get_default_modifier_delay")]
+    pub modifier_delay: u16,
+    // Type CRLF and lone CR as a single Enter
+    #[serde(default = "get_default_true")]
+    pub normalize_newlines: bool,
+    // Keep the modifier held between consecutive characters that need the same modifier
+    #[serde(default)]
+    pub skip_modifier_repress: bool,
+}
+
+fn get_default_key_delay() -> u16 {
+    DEFAULT_KEY_DELAY
+}
+
+fn get_default_modifier_delay() -> u16 {
+    DEFAULT_MODIFIER_DELAY
+}
+
+fn get_default_true() -> bool {
+    true
+}
+
+impl Default for TypingConfig {
+    fn default() -> Self {
+        Self {
+            key_delay: DEFAULT_KEY_DELAY,
+            modifier_delay: DEFAULT_MODIFIER_DELAY,
+            normalize_newlines: true,
+            skip_modifier_repress: false,
+        }
+    }
+}
+
+/// Type the ASCII text with the layout, non-ASCII characters and characters not on the layout are
+/// skipped. Dropping the future stops typing, the caller must release the keys in that case.
+#[cfg(feature = "clipboard")]
+pub async fn type_text(text: &[u8], layout: crate::TypingLayout, config: &TypingConfig) {
+    use embassy_time::{Duration, Timer};
+
+    use crate::{HidReport, IndicatorStatus, send_hid_report, set_indicator_status};
+
+    let key_delay = Duration::from_millis(config.key_delay as u64);
+    let modifier_delay = Duration::from_millis(config.modifier_delay as u64);
+    let mut report = crate::synergy_hid::KeyboardReport::default();
+    // Modifier currently held
+    let mut held = 0;
+    let mut progress = 0;
+    set_indicator_status(IndicatorStatus::Typing(0)).await;
+
+    for (i, &byte) in text.iter().enumerate() {
+        let byte = match byte {
+            b'\n' if config.normalize_newlines && i > 0 && text[i - 1] == b'\r' => continue,
+            b'\r' if config.normalize_newlines => b'\n',
+            // Ignore non-ASCII characters
+            0x80.. => continue,
+            _ => byte,
+        };
+        let [k, m] = layout.ascii_to_hid(byte);
+        if k == 0 {
+            continue;
+        }
+
+        if m != held {
+            if held != 0 {
+                send_hid_report(HidReport::keyboard(report.release(held))).await;
+                Timer::after(modifier_delay).await;
+            }
+            if m != 0 {
+                send_hid_report(HidReport::keyboard(report.press(m))).await;
+                Timer::after(modifier_delay).await;
+            }
+            held = m;
+        }
+        send_hid_report(HidReport::keyboard(report.press(k))).await;
+        Timer::after(key_delay).await;
+        send_hid_report(HidReport::keyboard(report.release(k))).await;
+        Timer::after(key_delay).await;
+        if held != 0 && !config.skip_modifier_repress {
+            send_hid_report(HidReport::keyboard(report.release(held))).await;
+            Timer::after(modifier_delay).await;
+            held = 0;
+        }
+
+        // Report in 5% steps, the indicator channel is small
+        let percent = ((i + 1) * 100 / text.len()) as u8;
+        if percent / 5 != progress / 5 {
+            progress = percent;
+            set_indicator_status(IndicatorStatus::Typing(progress)).await;
+        }
+    }
+    if held != 0 {
+        send_hid_report(HidReport::keyboard(report.release(held))).await;
+    }
+}
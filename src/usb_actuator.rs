@@ -1,34 +1,51 @@
 use log::{debug, info, warn};
 
+#[cfg(feature = "clipboard")]
+use crate::HotkeyTracker;
 use crate::{
-    Actuator, AppConfig, BarrierError, HidReport, IndicatorStatus, send_hid_report,
-    set_indicator_status,
+    Actuator, AppConfig, BarrierError, HidReport, IndicatorStatus, ScreenMapping, get_screen_size,
+    language::{LanguageChange, sync_language},
+    send_hid_report, set_indicator_status,
     synergy_hid::{ReportType, SynergyHid, modifier_mask_to_synergy},
 };
 
 pub struct UsbActuator {
+    // Logical screen size reported to the server
     width: u16,
     height: u16,
+    // Cursor position on the logical screen
     x: u16,
     y: u16,
+    mapping: ScreenMapping,
     hid: SynergyHid,
+    #[cfg(feature = "clipboard")]
+    hotkeys: HotkeyTracker,
 }
 
 impl UsbActuator {
     pub fn new() -> Self {
+        let (width, height) = get_screen_size();
         Self {
-            width: AppConfig::get().screen_width,
-            height: AppConfig::get().screen_height,
+            width,
+            height,
             x: 0,
             y: 0,
-            hid: SynergyHid::new(AppConfig::get().flip_wheel),
+            mapping: AppConfig::get().screen_mapping.clone(),
+            hid: SynergyHid::new(AppConfig::get().flip_wheel)
+                .with_apple_fn_key(AppConfig::get().apple_fn_key)
+                .with_mouse_button_map(&AppConfig::get().mouse_button_map),
+            #[cfg(feature = "clipboard")]
+            hotkeys: HotkeyTracker::new(),
         }
     }
 
     async fn send_report(&mut self, report: (ReportType, &[u8])) {
         match report.0 {
             ReportType::Keyboard => {
-                send_hid_report(HidReport::Keyboard(report.1.try_into().unwrap())).await;
+                let report = report.1.try_into().unwrap();
+                #[cfg(feature = "clipboard")]
+                crate::clipboard::set_server_keyboard_report(report);
+                send_hid_report(HidReport::Keyboard(report)).await;
             }
             ReportType::Mouse => {
                 send_hid_report(HidReport::Mouse(report.1.try_into().unwrap())).await;
@@ -36,6 +53,45 @@ impl UsbActuator {
             ReportType::Consumer => {
                 send_hid_report(HidReport::Consumer(report.1.try_into().unwrap())).await;
             }
+            ReportType::AppleVendor => {
+                send_hid_report(HidReport::AppleVendor(report.1.try_into().unwrap())).await;
+            }
+        }
+    }
+
+    /// Move the HID cursor to the position on the logical screen, without updating `x` and `y`.
+    async fn move_hid_cursor(&mut self, x: u16, y: u16) {
+        let (x, y) = self.mapping.to_hid(self.width, self.height, x, y);
+        let mut report = [0; 9];
+        let ret = self.hid.set_cursor_position(x, y, &mut report);
+        self.send_report(ret).await;
+    }
+
+    /// Handle the hotkey with the `index` in the list passed to `HotkeyTracker::key_down`.
+    #[cfg(feature = "clipboard")]
+    async fn hotkey(&mut self, index: usize) {
+        // The chord is not forwarded, release the keys already sent to the host
+        let mut report = [0; 9];
+        let ret = self.hid.clear(ReportType::Keyboard, &mut report);
+        self.send_report(ret).await;
+        match index {
+            0 => crate::request_paste(),
+            _ => crate::cancel_paste(),
+        }
+    }
+
+    /// Press the keys in order then release them in reverse order.
+    async fn press_chord(&mut self, keys: &[u16]) {
+        let mut report = [0; 9];
+        for key in keys {
+            if let Some(ret) = self.hid.chord_key_down(*key, &mut report) {
+                self.send_report(ret).await;
+            }
+        }
+        for key in keys.iter().rev() {
+            if let Some(ret) = self.hid.chord_key_up(*key, &mut report) {
+                self.send_report(ret).await;
+            }
         }
     }
 }
@@ -60,27 +116,36 @@ impl Actuator for UsbActuator {
     }
 
     async fn get_screen_size(&self) -> Result<(u16, u16), BarrierError> {
-        // TODO:
         Ok((self.width, self.height))
     }
 
+    async fn set_screen_size(&mut self, width: u16, height: u16) -> Result<(), BarrierError> {
+        info!("Screen size changed to {width}x{height}");
+        self.width = width;
+        self.height = height;
+        self.x = self.x.min(width);
+        self.y = self.y.min(height);
+        Ok(())
+    }
+
     async fn get_cursor_position(&self) -> Result<(u16, u16), BarrierError> {
         Ok((self.x, self.y))
     }
 
     async fn set_cursor_position(&mut self, x: u16, y: u16) -> Result<(), BarrierError> {
-        self.x = x;
-        self.y = y;
-        let mut report = [0; 9];
-        let ret = self.hid.set_cursor_position(x, y, &mut report);
-        self.send_report(ret).await;
+        self.x = x.min(self.width);
+        self.y = y.min(self.height);
+        self.move_hid_cursor(self.x, self.y).await;
         Ok(())
     }
 
     async fn move_cursor(&mut self, x: i16, y: i16) -> Result<(), BarrierError> {
         let (cx, cy) = self.get_cursor_position().await?;
-        self.set_cursor_position((cx as i32 + x as i32) as u16, (cy as i32 + y as i32) as u16)
-            .await
+        self.set_cursor_position(
+            (cx as i32 + x as i32).clamp(0, self.width as i32) as u16,
+            (cy as i32 + y as i32).clamp(0, self.height as i32) as u16,
+        )
+        .await
     }
 
     async fn mouse_down(&mut self, button: i8) -> Result<(), BarrierError> {
@@ -105,6 +170,19 @@ impl Actuator for UsbActuator {
     }
 
     async fn key_down(&mut self, key: u16, mask: u16, button: u16) -> Result<(), BarrierError> {
+        #[cfg(feature = "clipboard")]
+        {
+            let config = AppConfig::get();
+            if let Some(index) = self
+                .hotkeys
+                .key_down(key, &[&config.paste_hotkey, &config.cancel_paste_hotkey])
+            {
+                self.hotkey(index).await;
+                return Ok(());
+            }
+            // Typing into the host at the same time would garble both
+            crate::cancel_paste();
+        }
         let mut report = [0; 9];
         let ret = self.hid.key_down(key, mask, button, &mut report);
         self.send_report(ret).await;
@@ -123,6 +201,10 @@ impl Actuator for UsbActuator {
     }
 
     async fn key_up(&mut self, key: u16, mask: u16, button: u16) -> Result<(), BarrierError> {
+        #[cfg(feature = "clipboard")]
+        if self.hotkeys.key_up(key) {
+            return Ok(());
+        }
         let mut report = [0; 9];
         let ret = self.hid.key_up(key, mask, button, &mut report);
         self.send_report(ret).await;
@@ -132,28 +214,27 @@ impl Actuator for UsbActuator {
     async fn jiggle(&mut self) -> Result<(), BarrierError> {
         debug!("Jiggle the host");
         if self.hid.is_empty() {
-            let mut report = [0; 9];
-            let ret = self
-                .hid
-                .set_cursor_position(self.x + 1, self.y, &mut report);
-            self.send_report(ret).await;
-            let ret = self.hid.set_cursor_position(self.x, self.y, &mut report);
-            self.send_report(ret).await;
+            let x = if self.x < self.width {
+                self.x + 1
+            } else {
+                self.x.saturating_sub(1)
+            };
+            self.move_hid_cursor(x, self.y).await;
+            self.move_hid_cursor(self.x, self.y).await;
         }
         Ok(())
     }
 
     #[cfg(feature = "clipboard")]
-    async fn set_clipboard(
-        &mut self,
-        data: heapless::Vec<u8, { crate::constants::MAX_CLIPBOARD_SIZE }>,
-    ) -> Result<(), BarrierError> {
+    async fn set_clipboard(&mut self, data: crate::ClipboardData) -> Result<(), BarrierError> {
         crate::clipboard::set_clipboard(data).await;
         Ok(())
     }
 
     async fn enter(&mut self, x: u16, y: u16, mask: u16) -> Result<(), BarrierError> {
         info!("Entering, x: {x}, y: {y}, mask: {mask:#018b}");
+        #[cfg(feature = "clipboard")]
+        self.hotkeys.clear();
         // Server sends cursor position on entering, client should move the cursor
         self.set_cursor_position(x, y).await?;
         // Server sends modifier mask on entering, client should press the keys
@@ -168,6 +249,8 @@ impl Actuator for UsbActuator {
 
     async fn leave(&mut self) -> Result<(), BarrierError> {
         info!("Leaving");
+        #[cfg(feature = "clipboard")]
+        self.hotkeys.clear();
         let mut report = [0; 9];
         let ret = self.hid.clear(ReportType::Keyboard, &mut report);
         self.send_report(ret).await;
@@ -175,7 +258,53 @@ impl Actuator for UsbActuator {
         self.send_report(ret).await;
         let ret = self.hid.clear(ReportType::Consumer, &mut report);
         self.send_report(ret).await;
+        if AppConfig::get().apple_fn_key.is_some() {
+            let ret = self.hid.clear(ReportType::AppleVendor, &mut report);
+            self.send_report(ret).await;
+        }
         set_indicator_status(IndicatorStatus::ServerConnected).await;
         Ok(())
     }
+
+    async fn screensaver(&mut self, active: bool) -> Result<(), BarrierError> {
+        info!(
+            "Server screensaver {}",
+            if active { "activated" } else { "deactivated" }
+        );
+        let config = AppConfig::get();
+        if active && !config.screensaver_lock_chord.is_empty() {
+            self.press_chord(&config.screensaver_lock_chord).await;
+        } else if !active && config.screensaver_wake {
+            self.jiggle().await?;
+        }
+        Ok(())
+    }
+
+    async fn language_sync(&mut self, languages: &str) -> Result<(), BarrierError> {
+        info!("Server keyboard languages: '{languages}'");
+        let config = AppConfig::get();
+        let configured = config
+            .language_sync
+            .iter()
+            .map(|entry| entry.language.as_str());
+        let (index, switch) = match sync_language(configured, languages) {
+            LanguageChange::None => return Ok(()),
+            LanguageChange::Initial(index) => (index, false),
+            LanguageChange::Switch(index) => (index, true),
+        };
+        let entry = &config.language_sync[index];
+        if switch && !entry.chord.is_empty() {
+            self.press_chord(&entry.chord).await;
+        }
+        #[cfg(feature = "clipboard")]
+        if let Some(layout) = entry.typing_layout {
+            crate::set_typing_layout(layout);
+        }
+        Ok(())
+    }
+
+    async fn secure_input(&mut self, app: &str) -> Result<(), BarrierError> {
+        info!("Secure input enabled on the server by '{app}'");
+        Ok(())
+    }
 }